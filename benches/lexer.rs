@@ -0,0 +1,94 @@
+//! Lexer throughput benchmark.
+//! Run with `cargo bench --bench lexer`.
+
+extern crate lua5;
+
+use std::time::{Duration, Instant};
+use lua5::lexer::Lexer;
+
+/// A representative sample of Lua code, exercising every kind of token.
+const SAMPLE: &str = r#"#!/usr/bin/env lua
+-- Utilities for working with tables.
+--[[
+  Long comments span multiple lines
+  and may contain ]] without the right level.
+]]
+local M = {}
+
+function M.memoize(fn)
+    fn = fn or function(x) return nil end
+    return setmetatable({}, {
+        __index = function(t, k) local val = fn(k) t[k] = val return val end,
+        __call = function(t, k) return t[k] end,
+    })
+end
+
+function M.map(tbl, fn, ...)
+    local result = {}
+    for i = 1, #tbl do
+        result[i] = fn(tbl[i], ...)
+    end
+    return result
+end
+
+local function checksum(str)
+    local sum = 0x811C9DC5
+    for i = 1, #str do
+        sum = ((sum ~ str:byte(i)) * 0x01000193) & 0xFFFFFFFF
+    end
+    return sum >> 1 << 1, sum // 2, sum % 7, 2 ^ 0.5, 1e-3, 0x1.8p3
+end
+
+local template = [==[
+<html><body>{{ content }}</body></html>
+]==]
+
+M.escapes = { "tab\there", 'quote\'s', "\x41\65\u{20AC}", "line\z
+              continued" }
+
+::continue::
+while M.running and not M.stopped do
+    if M.count >= 10 then break elseif M.count <= 0 then goto continue end
+    M.count = M.count - 1
+    repeat M.count = M.count + 1 until M.count ~= 5 or M.count == 6
+end
+
+return M
+"#;
+
+/// The approximate size of the generated corpus, in bytes.
+const CORPUS_SIZE: usize = 8 * 1024 * 1024;
+
+/// The number of times the corpus is lexed.
+const ITERATIONS: u32 = 10;
+
+fn main() {
+    // The hashbang is only valid at the very beginning of the source.
+    let body = &SAMPLE[SAMPLE.find('\n').unwrap_or(0)..];
+    let mut corpus = String::with_capacity(CORPUS_SIZE + SAMPLE.len());
+    corpus.push_str(SAMPLE);
+    while corpus.len() < CORPUS_SIZE {
+        corpus.push_str(body);
+    }
+
+    let mut tokens = 0;
+    let mut elapsed = Duration::default();
+    for _ in 0..ITERATIONS {
+        let now = Instant::now();
+        tokens = 0;
+        for res in Lexer::new(&corpus) {
+            res.expect("the corpus lexes");
+            tokens += 1;
+        }
+        elapsed += now.elapsed();
+    }
+
+    let seconds = elapsed.as_secs_f64() / f64::from(ITERATIONS);
+    println!("lexed {} bytes ({} tokens) in {:.3} ms per iteration",
+             corpus.len(),
+             tokens,
+             seconds * 1000.0);
+    println!("throughput: {:.1} MiB/s, {:.1} Mtokens/s",
+             corpus.len() as f64 / seconds / (1024.0 * 1024.0),
+             tokens as f64 / seconds / 1_000_000.0);
+}
@@ -1,5 +0,0 @@
-pub trait Visitor<T> {
-    fn visit_name(&mut self, val: &Name) -> T;
-}
-
-pub struct Name(String);
\ No newline at end of file
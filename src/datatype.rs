@@ -1,9 +1,10 @@
 #![allow(dead_code)]
 
 use std::fmt;
+use std::rc::Rc;
 
 // Reference ID counter.
-static mut ref_id_counter: u32 = 0;
+static mut REF_ID_COUNTER: u32 = 0;
 
 /// A data type.
 #[derive(Debug, Clone)]
@@ -11,8 +12,13 @@ pub enum DataType {
     Nil,
     Void,
     Boolean(bool),
-    Number(f64),
-    StaticString(String),
+    /// An integer number.
+    Integer(i64),
+    /// A floating-point number.
+    Float(f64),
+    /// A string, shared between all values that refer to it.
+    /// Lua strings are arbitrary byte sequences, so this is not necessarily UTF-8.
+    StaticString(Rc<[u8]>),
     Function,
     Table,
     Tuple,
@@ -24,29 +30,28 @@ pub enum DataType {
 
 impl fmt::Display for DataType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.clone().to_error_type_string())
+        write!(f, "{}", self.to_error_type_string())
     }
 }
 
 impl DataType {
     /// Determines whether the specified data type can have type metatables.
     pub fn can_have_type_metatables(data_type: DataType) -> bool {
-        match data_type {
-            DataType::Nil |
-            DataType::Void |
-            DataType::Boolean(_) |
-            DataType::Number(_) |
-            DataType::StaticString(_) |
-            DataType::Function => true,
-            _ => false,
-        }
+        matches!(data_type,
+                 DataType::Nil |
+                 DataType::Void |
+                 DataType::Boolean(_) |
+                 DataType::Integer(_) |
+                 DataType::Float(_) |
+                 DataType::StaticString(_) |
+                 DataType::Function)
     }
 
     /// Converts the data type to a string.
     /// Used by the `type(...)` Lua function.
-    pub fn to_error_type_string(self) -> String {
-        let result: String = match self {
-            DataType::Void => format!("no value"),
+    pub fn to_error_type_string(&self) -> String {
+        let result: String = match *self {
+            DataType::Void => "no value".to_string(),
             DataType::Tuple |
             DataType::TailCallRequest |
             DataType::YieldRequest => format!("internal<{:?}>", self),
@@ -69,11 +74,11 @@ impl Default for Value {
     fn default() -> Value {
         let ref_id: u32;
         unsafe {
-            ref_id = ref_id_counter;
-            ref_id_counter += 1;
+            ref_id = REF_ID_COUNTER;
+            REF_ID_COUNTER += 1;
         }
         Value {
-            ref_id: ref_id,
+            ref_id,
             read_only: false,
             hash_code: -1,
             data_type: DataType::Void,
@@ -88,10 +93,13 @@ impl Value {
     fn new_boolean(val: bool) -> Value {
         Value { data_type: DataType::Boolean(val), ..Default::default() }
     }
-    fn new_number(val: f64) -> Value {
-        Value { data_type: DataType::Number(val), ..Default::default() }
+    fn new_integer(val: i64) -> Value {
+        Value { data_type: DataType::Integer(val), ..Default::default() }
+    }
+    fn new_float(val: f64) -> Value {
+        Value { data_type: DataType::Float(val), ..Default::default() }
     }
-    fn new_string(val: String) -> Value {
+    fn new_string(val: Rc<[u8]>) -> Value {
         Value { data_type: DataType::StaticString(val), ..Default::default() }
     }
 }
\ No newline at end of file
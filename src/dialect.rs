@@ -0,0 +1,85 @@
+#![deny(dead_code)]
+#![deny(missing_docs)]
+
+//! Language dialects.
+//! Controls which version of Lua and which extensions are accepted by the lexer and parser.
+
+use std::fmt;
+
+/// A version of the Lua language.
+/// Later versions compare greater than earlier ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Dialect {
+    /// Lua 5.1.
+    Lua51,
+    /// Lua 5.2, which adds `goto`, labels and the `\x` and `\z` escapes.
+    Lua52,
+    /// Lua 5.3, which adds integers, bitwise operators, `//` and the `\u{XXX}` escape.
+    Lua53,
+    /// Lua 5.4, which adds `<const>` and `<close>` variables.
+    Lua54,
+}
+
+/// Implements `Default` for `Dialect`.
+impl Default for Dialect {
+    fn default() -> Dialect {
+        Dialect::Lua54
+    }
+}
+
+/// Implements `Display` for `Dialect`.
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Dialect::Lua51 => write!(f, "Lua 5.1"),
+            Dialect::Lua52 => write!(f, "Lua 5.2"),
+            Dialect::Lua53 => write!(f, "Lua 5.3"),
+            Dialect::Lua54 => write!(f, "Lua 5.4"),
+        }
+    }
+}
+
+/// Options for the lexer and parser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    /// The version of Lua to accept.
+    pub dialect: Dialect,
+    /// Whether identifiers are restricted to ASCII letters, digits and underscores,
+    /// like in the reference implementation.
+    /// Otherwise any alphanumeric Unicode character is accepted.
+    pub ascii_identifiers: bool,
+    /// Whether the lambda extension is enabled, which makes `|x, y| x + y`
+    /// shorthand for `function(x, y) return x + y end`.
+    pub lambda: bool,
+    /// Whether the string interpolation extension is enabled, which makes `$"a = {a}"`
+    /// shorthand for `("a = " .. tostring(a))`.
+    pub interpolation: bool,
+    /// Whether the compound assignment extension is enabled, which makes `a.b += 1`
+    /// shorthand for `a.b = a.b + 1`, evaluating `a` only once and reading `a.b` before the value.
+    pub compound_assignment: bool,
+}
+
+/// Implements `Options`.
+impl Options {
+    /// Constructs options that accept exactly the given version of Lua.
+    pub fn new(dialect: Dialect) -> Options {
+        Options {
+            dialect,
+            ascii_identifiers: true,
+            lambda: false,
+            interpolation: false,
+            compound_assignment: false,
+        }
+    }
+}
+
+/// Implements `Default` for `Options`.
+/// Accepts Lua 5.4 with Unicode identifiers and no extensions.
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            ascii_identifiers: false,
+            ..Options::new(Dialect::default())
+        }
+    }
+}
@@ -0,0 +1,715 @@
+#![deny(dead_code)]
+#![deny(missing_docs)]
+
+//! Source formatting.
+//! Prints Lua source in a canonical layout that parses to the same AST, keeping its comments.
+
+use std::error;
+use std::fmt;
+use dialect::Options;
+use lexer::{LexError, Lexeme, Lexer, Span};
+use lua::{ArithmeticOp, BitwiseOp, LogicalOp, LuaOperation};
+use parser::ast::*;
+use parser::{ParseError, Parser, UNARY_PRIORITY};
+use token::{Keyword, Quoted, Token};
+
+/// The indentation of nested blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indent {
+    /// The given number of spaces per level.
+    Spaces(usize),
+    /// One tab per level, which counts as four columns.
+    Tabs,
+}
+
+/// The preferred quotes for string literals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteStyle {
+    /// Double quotes, `"like this"`.
+    Double,
+    /// Single quotes, `'like this'`.
+    Single,
+}
+
+/// Options for the formatter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// The dialect and extensions to accept in the source.
+    pub lua: Options,
+    /// The indentation of nested blocks.
+    pub indent: Indent,
+    /// The number of columns that lines should not exceed.
+    /// Table constructors and argument lists that do not fit are split with one item per line,
+    /// but nothing else is, so longer lines remain possible.
+    pub line_width: usize,
+    /// The preferred quotes for strings.
+    /// Strings that only contain the preferred quotes use the other ones instead.
+    pub quote_style: QuoteStyle,
+    /// Whether the last field of a table constructor that is split across lines is followed by a separator.
+    pub trailing_separator: bool,
+}
+
+/// Implements `Default` for `FormatOptions`.
+/// Indents by four spaces, prefers double quotes and limits lines to 100 columns.
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions {
+            lua: Options::default(),
+            indent: Indent::Spaces(4),
+            line_width: 100,
+            quote_style: QuoteStyle::Double,
+            trailing_separator: false,
+        }
+    }
+}
+
+/// An error that keeps source from being formatted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatError {
+    /// The source could not be lexed.
+    Lex(LexError),
+    /// The source could not be parsed.
+    Parse(ParseError),
+}
+
+/// Implements `Display` for `FormatError`.
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FormatError::Lex(ref err) => write!(f, "{}", err),
+            FormatError::Parse(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Implements `Error` for `FormatError`.
+impl error::Error for FormatError {}
+
+/// Implements `From<LexError>` for `FormatError`.
+impl From<LexError> for FormatError {
+    fn from(err: LexError) -> FormatError {
+        FormatError::Lex(err)
+    }
+}
+
+/// Implements `From<ParseError>` for `FormatError`.
+impl From<ParseError> for FormatError {
+    fn from(err: ParseError) -> FormatError {
+        FormatError::Parse(err)
+    }
+}
+
+/// Formats Lua source.
+/// Comments between statements keep their place and at most one blank line is kept between them.
+/// Comments between the items of a table or argument list are kept with the items, which are then printed one per line.
+/// Other comments within a statement are moved to their own line after it, unless one trails it on the same line.
+/// Extensions are printed in the form they are shorthand for.
+pub fn format(src: &str, options: FormatOptions) -> Result<String, FormatError> {
+    let tokens = Lexer::with_options(src, options.lua).collect::<Result<Vec<_>, _>>()?;
+    let block = Parser::with_options(&tokens, options.lua).parse()?;
+    let comments = comments(src, &tokens);
+    let elses = tokens.iter()
+        .filter(|lexeme| lexeme.0 == Token::Keyword(Keyword::Else))
+        .map(|lexeme| lexeme.2.start)
+        .collect::<Vec<_>>();
+    let mut printer = Printer {
+        src,
+        options,
+        comments: &comments,
+        elses: &elses,
+        next: 0,
+        last: 0,
+        level: 0,
+        out: String::new(),
+    };
+    if let Some(hashbang) = tokens.iter().find(|lexeme| matches!(lexeme.0, Token::Hashbang(_))) {
+        printer.write(&hashbang.0.to_string());
+    }
+    printer.print_stmts(&block, src.len());
+    if !printer.out.is_empty() {
+        printer.out.push('\n');
+    }
+    Ok(printer.out)
+}
+
+/// A comment in the source.
+struct Comment {
+    /// The canonical spelling.
+    text: String,
+    span: Span,
+    /// The end of the token that the comment follows on the same line, not counting semicolons.
+    trails: Option<usize>,
+}
+
+/// Collects the comments in the source.
+fn comments(src: &str, tokens: &[Lexeme]) -> Vec<Comment> {
+    let mut comments = Vec::new();
+    let mut prev: Option<Span> = None;
+    let mut anchor = None;
+    for lexeme in tokens {
+        match lexeme.0 {
+            Token::Comment(_) => {
+                let trails = match prev {
+                    Some(prev) if !src[prev.end..lexeme.2.start].contains(&['\n', '\r'][..]) => anchor,
+                    _ => None,
+                };
+                comments.push(Comment {
+                    text: lexeme.0.to_string(),
+                    span: lexeme.2,
+                    trails,
+                });
+            }
+            Token::Hashbang(_) => {}
+            Token::Semicolon => prev = Some(lexeme.2),
+            _ => {
+                prev = Some(lexeme.2);
+                anchor = Some(lexeme.2.end);
+            }
+        }
+    }
+    comments
+}
+
+/// Gets the number of columns taken by a line of text.
+fn width(line: &str) -> usize {
+    line.chars().map(|chr| if chr == '\t' { 4 } else { 1 }).sum()
+}
+
+/// Splits a binary operation into its operator and operands.
+fn binary(expr: &Expr) -> Option<(BinaryOp, &Expr, &Expr)> {
+    match expr.kind {
+        ExprKind::Operation(op, ref lhs, ref rhs) => Some((BinaryOp::Operation(op), lhs, rhs)),
+        ExprKind::Concat(ref lhs, ref rhs) => Some((BinaryOp::Concat, lhs, rhs)),
+        ExprKind::And(ref lhs, ref rhs) => Some((BinaryOp::And, lhs, rhs)),
+        ExprKind::Or(ref lhs, ref rhs) => Some((BinaryOp::Or, lhs, rhs)),
+        _ => None,
+    }
+}
+
+/// Splits a unary operation into its operator and operand.
+fn unary(expr: &Expr) -> Option<(&'static str, &Expr)> {
+    match expr.kind {
+        ExprKind::Unary(op, ref operand) => Some((arithmetic_operator(op), operand)),
+        ExprKind::Not(ref operand) => Some(("not ", operand)),
+        ExprKind::Len(ref operand) => Some(("#", operand)),
+        _ => None,
+    }
+}
+
+/// Gets the priority with which an operation binds its left and right operand.
+/// Unary operations have no left operand, so nothing binds more tightly there.
+fn priority(expr: &Expr) -> Option<(u8, u8)> {
+    match binary(expr) {
+        Some((op, ..)) => Some(op.priority()),
+        None => unary(expr).map(|_| (u8::MAX, UNARY_PRIORITY)),
+    }
+}
+
+/// Gets the spelling of an arithmetic operator.
+fn arithmetic_operator(op: ArithmeticOp) -> &'static str {
+    match op {
+        ArithmeticOp::Add => "+",
+        ArithmeticOp::Sub | ArithmeticOp::Unm => "-",
+        ArithmeticOp::Mul => "*",
+        ArithmeticOp::Mod => "%",
+        ArithmeticOp::Pow => "^",
+        ArithmeticOp::Div => "/",
+        ArithmeticOp::IDiv => "//",
+        ArithmeticOp::Shl => "<<",
+        ArithmeticOp::Shr => ">>",
+        ArithmeticOp::BitwiseOp(BitwiseOp::And) => "&",
+        ArithmeticOp::BitwiseOp(BitwiseOp::Or) => "|",
+        ArithmeticOp::BitwiseOp(BitwiseOp::Xor) | ArithmeticOp::BitwiseOp(BitwiseOp::Not) => "~",
+    }
+}
+
+/// Gets the spelling of a binary operator.
+fn operator(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Operation(LuaOperation::Arithmetic(op)) => arithmetic_operator(op),
+        BinaryOp::Operation(LuaOperation::Logical(op)) => {
+            match op {
+                LogicalOp::Equal => "==",
+                LogicalOp::NotEqual => "~=",
+                LogicalOp::LessThan => "<",
+                LogicalOp::LessThanOrEqual => "<=",
+                LogicalOp::GreaterThan => ">",
+                LogicalOp::GreaterThanOrEqual => ">=",
+            }
+        }
+        BinaryOp::Concat => "..",
+        BinaryOp::And => "and",
+        BinaryOp::Or => "or",
+    }
+}
+
+/// An item in a list between delimiters, which comments can be attached to.
+trait Item {
+    /// Gets the location in the source.
+    fn span(&self) -> Span;
+}
+
+/// Implements `Item` for `Expr`.
+impl Item for Expr {
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+/// Implements `Item` for `TableField`.
+impl Item for TableField {
+    fn span(&self) -> Span {
+        match *self {
+            TableField::Positional(ref val) => val.span,
+            TableField::Named(ref name, ref val) => Span {
+                start: name.span.start,
+                end: val.span.end,
+            },
+            TableField::Bracketed(ref key, ref val) => Span {
+                start: key.span.start,
+                end: val.span.end,
+            },
+        }
+    }
+}
+
+/// Prints the AST of a source, interleaved with its comments.
+struct Printer<'a> {
+    src: &'a str,
+    options: FormatOptions,
+    comments: &'a [Comment],
+    /// The starts of the `else` keywords, in order.
+    elses: &'a [usize],
+    /// The index of the first comment that has not been printed.
+    next: usize,
+    /// The end of the last statement, item between delimiters or comment that has been printed.
+    last: usize,
+    /// The nesting depth of blocks, which is 0 in the main chunk.
+    level: usize,
+    out: String,
+}
+
+/// Implements `Printer`.
+impl<'a> Printer<'a> {
+    /// Appends text to the current line.
+    fn write(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+    /// Starts a new line at the current indentation.
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.level {
+            match self.options.indent {
+                Indent::Spaces(n) => self.out.extend((0..n).map(|_| ' ')),
+                Indent::Tabs => self.out.push('\t'),
+            }
+        }
+    }
+    /// Determines whether the given number of columns fit on the current line.
+    fn fits(&self, columns: usize) -> bool {
+        let line = &self.out[self.out.rfind('\n').map_or(0, |idx| idx + 1)..];
+        width(line) + columns <= self.options.line_width
+    }
+    /// Gets the number of columns up to the first line break that printing would take.
+    fn measure<F: FnOnce(&mut Printer<'a>)>(&self, print: F) -> usize {
+        let mut scratch = Printer {
+            comments: &[],
+            next: 0,
+            out: String::new(),
+            ..*self
+        };
+        print(&mut scratch);
+        width(scratch.out.lines().next().unwrap_or(""))
+    }
+    /// Starts a statement or comment on a new line,
+    /// after a blank line if there was one in the source and it is not the first in its block.
+    fn begin_item(&mut self, start: usize, first: bool) {
+        if self.out.is_empty() {
+            return;
+        }
+        let blank = self.src.get(self.last..start).is_some_and(|gap| gap.matches('\n').count() > 1);
+        if !first && blank {
+            self.out.push('\n');
+        }
+        self.newline();
+    }
+    /// Prints the comments before an offset, each on its own line.
+    fn print_comments(&mut self, end: usize, first: &mut bool) {
+        let comments = self.comments;
+        while let Some(comment) = comments.get(self.next).filter(|comment| comment.span.start < end) {
+            self.begin_item(comment.span.start, *first);
+            *first = false;
+            self.write(&comment.text);
+            self.last = self.last.max(comment.span.end);
+            self.next += 1;
+        }
+    }
+    /// Prints the statements of a block at the current indentation, followed by the comments before `end`.
+    fn print_stmts(&mut self, block: &Block, end: usize) {
+        let mut first = true;
+        for stmt in &block.0 {
+            self.print_comments(stmt.span.start, &mut first);
+            self.begin_item(stmt.span.start, first);
+            first = false;
+            let start = self.out.len();
+            self.print_stmt(stmt);
+            // A statement starting with a parenthesis would continue a call on the previous line.
+            if self.out[start..].starts_with('(') {
+                self.out.insert(start, ';');
+            }
+            self.last = self.last.max(stmt.span.end);
+            self.finish_stmt(stmt.span);
+        }
+        self.print_comments(end, &mut first);
+    }
+    /// Prints the comments left within a statement on their own lines,
+    /// or the comment that trails it on the same line.
+    fn finish_stmt(&mut self, span: Span) {
+        let comments = self.comments;
+        match comments.get(self.next) {
+            Some(comment) if comment.span.start < span.end => self.print_comments(span.end, &mut false),
+            Some(comment) if comment.trails.is_some_and(|end| end <= span.end) => {
+                self.write(" ");
+                self.write(&comment.text);
+                self.last = self.last.max(comment.span.end);
+                self.next += 1;
+            }
+            _ => {}
+        }
+    }
+    /// Prints an indented block followed by the keyword that closes it on its own line.
+    /// Blocks that are empty, even of comments, are closed on the same line.
+    fn print_body(&mut self, block: &Block, end: usize, close: &str) {
+        let comments = self.comments;
+        if block.0.is_empty() && comments.get(self.next).is_none_or(|comment| comment.span.start >= end) {
+            self.write(" ");
+            self.write(close);
+            return;
+        }
+        self.level += 1;
+        self.print_stmts(block, end);
+        self.level -= 1;
+        self.newline();
+        self.write(close);
+    }
+    /// Prints a statement.
+    fn print_stmt(&mut self, stmt: &Stmt) {
+        let end = stmt.span.end;
+        match stmt.kind {
+            StmtKind::Do(ref block) => {
+                self.write("do");
+                self.print_body(block, end, "end");
+            }
+            StmtKind::Set(ref targets, ref exprs) => {
+                self.print_list(targets, Printer::print_lvalue);
+                self.write(" = ");
+                self.print_list(exprs, Printer::print_expr);
+            }
+            StmtKind::Expr(ref expr) => self.print_expr(expr),
+            StmtKind::While(ref cond, ref block) => {
+                self.write("while ");
+                self.print_expr(cond);
+                self.write(" do");
+                self.print_body(block, end, "end");
+            }
+            StmtKind::Repeat(ref cond, ref block) => {
+                self.write("repeat");
+                self.print_body(block, cond.span.start, "until");
+                self.write(" ");
+                self.print_expr(cond);
+            }
+            StmtKind::If(ref branches, ref otherwise) => {
+                for (idx, (cond, block)) in branches.iter().enumerate() {
+                    self.write(if idx == 0 { "if " } else { " " });
+                    self.print_expr(cond);
+                    self.write(" then");
+                    match (branches.get(idx + 1), otherwise) {
+                        (Some(next), _) => self.print_body(block, next.0.span.start, "elseif"),
+                        (None, Some(_)) => {
+                            // Comments after `else` belong to the else branch, even if it is empty.
+                            let last = block.0.last().map_or(cond.span.end, |stmt| stmt.span.end);
+                            let idx = self.elses.partition_point(|&pos| pos < last);
+                            self.print_body(block, self.elses.get(idx).map_or(end, |&pos| pos), "else");
+                        }
+                        (None, None) => self.print_body(block, end, "end"),
+                    }
+                }
+                if let Some(ref block) = *otherwise {
+                    self.print_body(block, end, "end");
+                }
+            }
+            StmtKind::ForNum(ref var, ref start, ref limit, ref step, ref block) => {
+                self.write("for ");
+                self.print_name(var);
+                self.write(" = ");
+                self.print_expr(start);
+                self.write(", ");
+                self.print_expr(limit);
+                if let Some(ref step) = *step {
+                    self.write(", ");
+                    self.print_expr(step);
+                }
+                self.write(" do");
+                self.print_body(block, end, "end");
+            }
+            StmtKind::ForIn(ref names, ref exprs, ref block) => {
+                self.write("for ");
+                self.print_list(names, Printer::print_name);
+                self.write(" in ");
+                self.print_list(exprs, Printer::print_expr);
+                self.write(" do");
+                self.print_body(block, end, "end");
+            }
+            StmtKind::Function(ref name, ref body) => {
+                self.write("function ");
+                for (idx, name) in name.path.iter().enumerate() {
+                    if idx > 0 {
+                        self.write(".");
+                    }
+                    self.print_name(name);
+                }
+                if let Some(ref method) = name.method {
+                    self.write(":");
+                    self.print_name(method);
+                }
+                self.print_function(body, end);
+            }
+            StmtKind::LocalFunction(ref name, ref body) => {
+                self.write("local function ");
+                self.print_name(name);
+                self.print_function(body, end);
+            }
+            StmtKind::Local(ref names, ref exprs) => {
+                self.write("local ");
+                self.print_list(names, |printer, &(ref name, attrib)| {
+                    printer.print_name(name);
+                    match attrib {
+                        Some(Attrib::Const) => printer.write(" <const>"),
+                        Some(Attrib::Close) => printer.write(" <close>"),
+                        None => {}
+                    }
+                });
+                if !exprs.is_empty() {
+                    self.write(" = ");
+                    self.print_list(exprs, Printer::print_expr);
+                }
+            }
+            StmtKind::Goto(ref label) => {
+                self.write("goto ");
+                self.print_name(label);
+            }
+            StmtKind::Label(ref label) => {
+                self.write("::");
+                self.print_name(label);
+                self.write("::");
+            }
+            StmtKind::Return(ref exprs) => {
+                self.write("return");
+                if !exprs.is_empty() {
+                    self.write(" ");
+                    self.print_list(exprs, Printer::print_expr);
+                }
+            }
+            StmtKind::Break => self.write("break"),
+            // Only parsing with recovery produces these, so they are kept as they were.
+            StmtKind::Error => {
+                let src = self.src;
+                self.write(&src[stmt.span.start..stmt.span.end]);
+            }
+        }
+    }
+    /// Prints items separated by commas on the current line.
+    fn print_list<T, F: Fn(&mut Printer<'a>, &T)>(&mut self, items: &[T], print: F) {
+        for (idx, item) in items.iter().enumerate() {
+            if idx > 0 {
+                self.write(", ");
+            }
+            print(self, item);
+        }
+    }
+    /// Prints items between delimiters on the current line if the line fits, or one per line otherwise.
+    /// Items are always printed one per line if there are comments between the delimiters, which end at `end`.
+    /// Comments before an item are printed on their own lines, and a comment that trails an item or its comma
+    /// on the same line keeps trailing it.
+    fn print_delimited<T: Item>(&mut self,
+                                open: &str,
+                                items: &[T],
+                                close: &str,
+                                trailing: bool,
+                                end: usize,
+                                print: fn(&mut Printer<'a>, &T)) {
+        self.write(open);
+        let comments = self.comments;
+        let commented = comments.get(self.next).is_some_and(|comment| comment.span.start < end);
+        if !items.is_empty() && !commented {
+            let columns = self.measure(|printer| printer.print_list(items, print));
+            if self.fits(columns + close.len()) {
+                self.print_list(items, print);
+                self.write(close);
+                return;
+            }
+        }
+        if !items.is_empty() || commented {
+            self.level += 1;
+            let mut first = true;
+            for (idx, item) in items.iter().enumerate() {
+                let span = item.span();
+                self.print_comments(span.start, &mut first);
+                self.begin_item(span.start, first);
+                first = false;
+                print(self, item);
+                if idx + 1 < items.len() || trailing {
+                    self.write(",");
+                }
+                self.last = self.last.max(span.end);
+                self.print_comments(span.end, &mut first);
+                let limit = items.get(idx + 1).map_or(end, |next| next.span().start);
+                match comments.get(self.next) {
+                    Some(comment) if comment.span.start < limit && comment.trails.is_some_and(|at| at >= span.end) => {
+                        self.write(" ");
+                        self.write(&comment.text);
+                        self.last = self.last.max(comment.span.end);
+                        self.next += 1;
+                    }
+                    _ => {}
+                }
+            }
+            self.print_comments(end, &mut first);
+            self.level -= 1;
+            self.newline();
+        }
+        self.write(close);
+    }
+    /// Prints a name.
+    fn print_name(&mut self, name: &Name) {
+        self.write(&name.symbol.to_text());
+    }
+    /// Prints a string literal.
+    fn print_string(&mut self, val: &[u8]) {
+        let (preferred, other) = match self.options.quote_style {
+            QuoteStyle::Double => ('"', '\''),
+            QuoteStyle::Single => ('\'', '"'),
+        };
+        let quote = match val.contains(&(preferred as u8)) && !val.contains(&(other as u8)) {
+            true => other,
+            false => preferred,
+        };
+        self.write(&Quoted(val, quote).to_string());
+    }
+    /// Prints the parameters and the body of a function.
+    fn print_function(&mut self, body: &FunctionBody, end: usize) {
+        self.write("(");
+        self.print_list(&body.params, Printer::print_name);
+        if body.varargs {
+            self.write(if body.params.is_empty() { "..." } else { ", ..." });
+        }
+        self.write(")");
+        self.print_body(&body.block, end, "end");
+    }
+    /// Prints the target of an assignment.
+    fn print_lvalue(&mut self, lvalue: &LValue) {
+        match *lvalue {
+            LValue::Name(ref name) => self.print_name(name),
+            LValue::Field(ref obj, ref name) => {
+                self.print_expr(obj);
+                self.write(".");
+                self.print_name(name);
+            }
+            LValue::Index(ref obj, ref key) => {
+                self.print_expr(obj);
+                self.write("[");
+                self.print_expr(key);
+                self.write("]");
+            }
+        }
+    }
+    /// Prints a field in a table constructor.
+    fn print_field(&mut self, field: &TableField) {
+        match *field {
+            TableField::Positional(ref val) => self.print_expr(val),
+            TableField::Named(ref name, ref val) => {
+                self.print_name(name);
+                self.write(" = ");
+                self.print_expr(val);
+            }
+            TableField::Bracketed(ref key, ref val) => {
+                self.write("[");
+                self.print_expr(key);
+                self.write("] = ");
+                self.print_expr(val);
+            }
+        }
+    }
+    /// Prints an operand, in parentheses if it would otherwise bind differently.
+    fn print_operand(&mut self, expr: &Expr, parens: bool) {
+        if parens {
+            self.write("(");
+            self.print_expr(expr);
+            self.write(")");
+        } else {
+            self.print_expr(expr);
+        }
+    }
+    /// Prints an expression.
+    fn print_expr(&mut self, expr: &Expr) {
+        match expr.kind {
+            ExprKind::Nil => self.write("nil"),
+            ExprKind::Dots => self.write("..."),
+            ExprKind::True => self.write("true"),
+            ExprKind::False => self.write("false"),
+            ExprKind::Integer(val) => self.write(&Token::Integer(val).to_string()),
+            ExprKind::Float(val) => self.write(&Token::Float(val).to_string()),
+            ExprKind::StaticString(ref val) => self.print_string(val),
+            ExprKind::Name(ref name) => self.print_name(name),
+            ExprKind::Paren(ref inner) => self.print_operand(inner, true),
+            ExprKind::Field(ref obj, ref name) => {
+                self.print_expr(obj);
+                self.write(".");
+                self.print_name(name);
+            }
+            ExprKind::Index(ref obj, ref key) => {
+                self.print_expr(obj);
+                self.write("[");
+                self.print_expr(key);
+                self.write("]");
+            }
+            ExprKind::Call(ref func, ref args) => {
+                self.print_expr(func);
+                self.print_delimited("(", args, ")", false, expr.span.end, Printer::print_expr);
+            }
+            ExprKind::MethodCall(ref obj, ref name, ref args) => {
+                self.print_expr(obj);
+                self.write(":");
+                self.print_name(name);
+                self.print_delimited("(", args, ")", false, expr.span.end, Printer::print_expr);
+            }
+            ExprKind::Function(ref body) => {
+                self.write("function");
+                self.print_function(body, expr.span.end);
+            }
+            ExprKind::Table(ref table) => {
+                let trailing = self.options.trailing_separator;
+                self.print_delimited("{", &table.0, "}", trailing, expr.span.end, Printer::print_field);
+            }
+            ExprKind::Operation(..) | ExprKind::Concat(..) | ExprKind::And(..) | ExprKind::Or(..) => {
+                let (op, lhs, rhs) = binary(expr).unwrap();
+                let (left, right) = op.priority();
+                self.print_operand(lhs, priority(lhs).is_some_and(|(_, prio)| left > prio));
+                self.write(" ");
+                self.write(operator(op));
+                self.write(" ");
+                self.print_operand(rhs, priority(rhs).is_some_and(|(prio, _)| prio <= right));
+            }
+            ExprKind::Unary(..) | ExprKind::Not(_) | ExprKind::Len(_) => {
+                let (op, operand) = unary(expr).unwrap();
+                self.write(op);
+                let start = self.out.len();
+                self.print_operand(operand, priority(operand).is_some_and(|(prio, _)| prio <= UNARY_PRIORITY));
+                // Two minus signs in a row would start a comment.
+                if op == "-" && self.out[start..].starts_with('-') {
+                    self.out.insert(start, ' ');
+                }
+            }
+        }
+    }
+}
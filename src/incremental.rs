@@ -0,0 +1,108 @@
+#![deny(dead_code)]
+#![deny(missing_docs)]
+
+//! Incremental lexical analysis.
+//! Updates a token stream after an edit by lexing only the affected region again.
+
+use std::ops::Range;
+use dialect::Options;
+use lexer::{Lexer, Lexeme, LexError, Span, TokenPosition};
+
+/// A text edit, which replaces a range of the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEdit<'a> {
+    /// The replaced range of the source before the edit.
+    pub span: Span,
+    /// The replacement.
+    pub replacement: &'a [u8],
+}
+
+/// Implements `TextEdit`.
+impl<'a> TextEdit<'a> {
+    /// Applies the edit to the source before the edit.
+    pub fn apply(&self, src: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(src.len() - self.span.len() + self.replacement.len());
+        buf.extend_from_slice(&src[..self.span.start]);
+        buf.extend_from_slice(self.replacement);
+        buf.extend_from_slice(&src[self.span.end..]);
+        buf
+    }
+}
+
+/// Updates the tokens of a source after an edit.
+///
+/// The tokens have to be the complete output of a `Lexer` with the given options over the
+/// source before the edit, and `src` is the source after the edit. Lexing restarts at the
+/// token before the edit and stops at the first token that starts where one of the old tokens
+/// after the edit started, since the rest of the stream is the same from there on and only
+/// has to be shifted. This also holds when the edit opens or closes a long string or comment.
+///
+/// Returns the range of tokens that were lexed again.
+/// On error, the tokens are left unchanged.
+pub fn relex(tokens: &mut Vec<Lexeme<'static>>,
+             src: &[u8],
+             edit: &TextEdit,
+             options: Options)
+             -> Result<Range<usize>, LexError> {
+    let old_end = edit.span.end;
+    let new_end = edit.span.start + edit.replacement.len();
+
+    // The lexer looks at most one byte past the end of a token, so tokens ending before the edit
+    // are not affected. Lexing restarts at the last of them, as its position is known.
+    let first = tokens
+        .iter()
+        .position(|lexeme| lexeme.2.end >= edit.span.start)
+        .unwrap_or(tokens.len());
+    let restart = first.saturating_sub(1);
+    let (offset, pos) = match first {
+        0 => (0, TokenPosition::default()),
+        _ => (tokens[restart].2.start, tokens[restart].1),
+    };
+
+    // The index of the next old token after the edit that the new stream might resynchronize at.
+    let mut candidate = tokens
+        .iter()
+        .position(|lexeme| lexeme.2.start >= old_end)
+        .unwrap_or(tokens.len());
+
+    let mut relexed = Vec::new();
+    let mut resync = None;
+    for res in Lexer::resume(src, options, offset, pos) {
+        let lexeme = res?;
+        if lexeme.2.start >= new_end {
+            let start = lexeme.2.start - new_end + old_end;
+            while candidate < tokens.len() && tokens[candidate].2.start < start {
+                candidate += 1;
+            }
+            if candidate < tokens.len() && tokens[candidate].2.start == start {
+                resync = Some((candidate, lexeme.1));
+                break;
+            }
+        }
+        relexed.push(lexeme.into_owned());
+    }
+
+    let end = restart + relexed.len();
+    match resync {
+        Some((candidate, new_pos)) => {
+            let old_pos = tokens[candidate].1;
+            tokens.splice(restart..candidate, relexed);
+            for lexeme in &mut tokens[end..] {
+                lexeme.move_by(&mut |pos, span| {
+                    span.start = span.start - old_end + new_end;
+                    span.end = span.end - old_end + new_end;
+                    // Only the tokens on the same line are moved horizontally.
+                    if pos.line == old_pos.line {
+                        pos.pos = pos.pos - old_pos.pos + new_pos.pos;
+                    }
+                    pos.line = pos.line - old_pos.line + new_pos.line;
+                });
+            }
+        }
+        None => {
+            tokens.truncate(restart);
+            tokens.extend(relexed);
+        }
+    }
+    Ok(restart..end)
+}
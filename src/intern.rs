@@ -0,0 +1,127 @@
+#![deny(dead_code)]
+#![deny(missing_docs)]
+
+//! String interning.
+//! Identifiers are stored once and referred to by cheap symbols.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ptr;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// The number of strings the interner holds before it first frees unused ones.
+const MIN_LIMIT: usize = 1024;
+
+/// An interned byte string.
+/// Symbols are equal if and only if their strings are equal, so comparing and hashing them
+/// is as cheap as for pointers. Cloning a symbol only counts a reference, and the string
+/// is freed once no symbol refers to it anymore.
+#[derive(Clone)]
+pub struct Symbol(Arc<[u8]>);
+
+/// The interner.
+/// It holds one reference to every string, which it drops once that is the only one left.
+struct Interner {
+    /// The interned strings.
+    strings: HashSet<Arc<[u8]>>,
+    /// The number of strings at which unused strings are freed.
+    limit: usize,
+}
+
+/// Gets the interner shared by the lexer, the parser and the runtime.
+/// It is only locked to intern strings, never to read them.
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(|| {
+        Mutex::new(Interner {
+            strings: HashSet::new(),
+            limit: MIN_LIMIT,
+        })
+    })
+}
+
+/// Implements `Symbol`.
+impl Symbol {
+    /// Interns a string, which is only copied if it is not interned already.
+    pub fn intern<S: AsRef<[u8]> + ?Sized>(val: &S) -> Symbol {
+        let val = val.as_ref();
+        let mut interner = interner().lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(val) = interner.strings.get(val) {
+            return Symbol(val.clone());
+        }
+        // Strings only referred to by the interner cannot be referred to again without interning
+        // them, which needs the lock, so they are safe to free. The limit keeps this amortized.
+        if interner.strings.len() >= interner.limit {
+            interner.strings.retain(|val| Arc::strong_count(val) > 1);
+            interner.limit = MIN_LIMIT.max(interner.strings.len() * 2);
+        }
+        let val = Arc::<[u8]>::from(val);
+        interner.strings.insert(val.clone());
+        Symbol(val)
+    }
+    /// Gets the interned string.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+    /// Gets the interned string as text, replacing invalid UTF-8 sequences.
+    /// Identifiers are always valid UTF-8.
+    pub fn to_text(&self) -> String {
+        String::from_utf8_lossy(self.as_bytes()).into_owned()
+    }
+}
+
+/// Implements `PartialEq` for `Symbol`.
+/// Equal strings are interned only once, so their symbols are the same reference.
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Implements `Eq` for `Symbol`.
+impl Eq for Symbol {}
+
+/// Implements `Hash` for `Symbol`.
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        ptr::hash(&*self.0, state)
+    }
+}
+
+/// Implements `PartialOrd` for `Symbol`.
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Symbol) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Implements `Ord` for `Symbol`.
+/// Symbols are ordered by their strings.
+impl Ord for Symbol {
+    fn cmp(&self, other: &Symbol) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+/// Implements `From<&'a str>` for `Symbol`.
+impl<'a> From<&'a str> for Symbol {
+    fn from(val: &'a str) -> Symbol {
+        Symbol::intern(val)
+    }
+}
+
+/// Implements `Debug` for `Symbol`.
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Symbol({:?})", self.to_text())
+    }
+}
+
+/// Implements `Display` for `Symbol`.
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_text())
+    }
+}
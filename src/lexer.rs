@@ -4,21 +4,88 @@
 //! The lexical analyser.
 //! Performs lexical analysis on Lua source code.
 
+use std::borrow::Cow;
+use std::error;
 use std::fmt;
-use std::str::Chars;
-use std::iter::Peekable;
-use token::{Token, Keyword};
+use std::io;
+use std::str;
+use dialect::{Dialect, Options};
+use intern::Symbol;
+use token::{Fragment, Token, Keyword};
 
 /// A lexical token with positional information.
-pub struct Lexeme(pub Token, pub TokenPosition);
+/// The token may borrow from the source, see `Lexeme::into_owned`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Lexeme<'a>(pub Token<'a>, pub TokenPosition, pub Span);
+
+/// Implements `Lexeme`.
+impl<'a> Lexeme<'a> {
+    /// Converts the lexeme into one that does not borrow from the source.
+    pub fn into_owned(self) -> Lexeme<'static> {
+        Lexeme(self.0.into_owned(), self.1, self.2)
+    }
+    /// Moves the lexeme, and the lexemes in an interpolated string, by applying `f`
+    /// to each of their positions and spans.
+    pub(crate) fn move_by<F: FnMut(&mut TokenPosition, &mut Span)>(&mut self, f: &mut F) {
+        if let Token::Interpolation(ref mut fragments) = self.0 {
+            for fragment in fragments {
+                if let Fragment::Expr(ref mut lexemes) = *fragment {
+                    for lexeme in lexemes {
+                        lexeme.move_by(f);
+                    }
+                }
+            }
+        }
+        f(&mut self.1, &mut self.2);
+    }
+}
+
+/// A range of bytes in the source, from `start` (inclusive) to `end` (exclusive).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Span {
+    /// The offset of the first byte.
+    pub start: usize,
+    /// The offset after the last byte.
+    pub end: usize,
+}
+
+/// Implements `Span`.
+impl Span {
+    /// Gets the number of bytes in the span.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+    /// Determines whether the span is empty.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+    /// Determines whether the span contains the byte at `offset`.
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+    /// Gets the source text covered by the span.
+    pub fn text<'a>(&self, src: &'a [u8]) -> &'a [u8] {
+        &src[self.start..self.end]
+    }
+}
+
+/// Implements `Display` for `Span`.
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
 
 /// Positional information for lexical tokens.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TokenPosition {
     /// The current line.
-    line: u32,
-    /// The position on the current line.
-    pos: u32,
+    pub(crate) line: u32,
+    /// The position on the current line, counted in characters.
+    pub(crate) pos: u32,
 }
 
 /// Implements `Default` for `TokenPosition`.
@@ -35,96 +102,364 @@ impl fmt::Display for TokenPosition {
     }
 }
 
+/// Implements `TokenPosition`.
+impl TokenPosition {
+    /// Gets the line, starting at 1.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+    /// Gets the position on the current line, starting at 0.
+    pub fn pos(&self) -> u32 {
+        self.pos
+    }
+}
+
+/// The kind of a lexical error.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexErrorKind {
+    /// An unknown escape sequence in a string literal, e.g. `\q`.
+    InvalidEscape(char),
+    /// A string literal that is not closed before the end of the line or stream.
+    UnterminatedString,
+    /// A long comment that is not closed before the end of the stream.
+    UnterminatedComment,
+    /// A `\x` escape sequence that is not followed by two hexadecimal digits.
+    InvalidHexEscape,
+    /// A `\ddd` escape sequence with a value above 255.
+    DecimalEscapeTooLarge,
+    /// A `\u` escape sequence that is missing its braces or digits.
+    MalformedUnicodeEscape,
+    /// A `\u{XXX}` escape sequence with a value above `7FFFFFFF`.
+    UnicodeEscapeTooLarge,
+    /// An identifier that is not valid UTF-8.
+    InvalidUtf8,
+    /// An opening long bracket with `=` signs but no second `[`, e.g. `[==`.
+    InvalidLongDelimiter,
+    /// A number literal that does not parse.
+    MalformedNumber(String),
+    /// A hashbang that is not at the very beginning of the source.
+    MisplacedHashbang,
+    /// A character that does not start any token.
+    UnexpectedCharacter(char),
+    /// A construct that is not supported by the selected dialect or options.
+    Unsupported(&'static str, Dialect),
+    /// An error while reading the source from a stream.
+    Io(io::ErrorKind),
+}
+
+/// Implements `Display` for `LexErrorKind`.
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LexErrorKind::InvalidEscape(chr) => write!(f, "invalid escape sequence `\\{}`", chr),
+            LexErrorKind::UnterminatedString => write!(f, "unfinished string"),
+            LexErrorKind::InvalidHexEscape => write!(f, "hexadecimal digit expected"),
+            LexErrorKind::DecimalEscapeTooLarge => write!(f, "decimal escape too large"),
+            LexErrorKind::MalformedUnicodeEscape => write!(f, "malformed `\\u{{XXX}}` escape sequence"),
+            LexErrorKind::UnicodeEscapeTooLarge => write!(f, "UTF-8 value too large"),
+            LexErrorKind::InvalidUtf8 => write!(f, "identifier is not valid UTF-8"),
+            LexErrorKind::UnterminatedComment => write!(f, "unfinished long comment"),
+            LexErrorKind::InvalidLongDelimiter => write!(f, "invalid long string delimiter"),
+            LexErrorKind::MalformedNumber(ref num) => write!(f, "malformed number near `{}`", num),
+            LexErrorKind::MisplacedHashbang => write!(f, "the hashbang has to be on the first line"),
+            LexErrorKind::UnexpectedCharacter(chr) => write!(f, "unexpected character `{}`", chr),
+            LexErrorKind::Unsupported(construct, dialect) => write!(f, "{} is not supported in {}", construct, dialect),
+            LexErrorKind::Io(kind) => write!(f, "cannot read source: {}", kind),
+        }
+    }
+}
+
+/// A lexical error with positional information.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    /// The kind of error.
+    pub kind: LexErrorKind,
+    /// The position at which the error was detected.
+    pub pos: TokenPosition,
+}
+
+/// Implements `Display` for `LexError`.
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.pos, self.kind)
+    }
+}
+
+/// Implements `Error` for `LexError`.
+impl error::Error for LexError {}
+
+/// Determines whether a byte is whitespace in the sense of the C locale.
+pub(crate) fn is_whitespace(chr: u8) -> bool {
+    matches!(chr, b' ' | b'\t' | b'\n' | b'\r' | b'\x0b' | b'\x0c')
+}
+
+/// Determines whether a byte can be part of an identifier.
+/// Non-ASCII bytes are accepted here and validated once the identifier is complete.
+fn is_ident(chr: u8) -> bool {
+    chr.is_ascii_alphanumeric() || chr == b'_' || !chr.is_ascii()
+}
+
+/// Converts a numeral to a number token, following the rules of the reference implementation.
+/// Decimal integers that do not fit into 64 bits become floats,
+/// while hexadecimal integers wrap around.
+/// Without `integers`, every numeral becomes a float, like before Lua 5.3.
+/// Returns `None` if the numeral is malformed.
+fn parse_number(buf: &str, integers: bool) -> Option<Token<'static>> {
+    if buf.starts_with("0x") || buf.starts_with("0X") {
+        return parse_hex_number(&buf[2..], integers);
+    }
+    if !integers || buf.contains(&['.', 'e', 'E'][..]) {
+        return buf.parse::<f64>().ok().map(Token::Float);
+    }
+    match buf.parse::<i64>() {
+        Ok(num) => Some(Token::Integer(num)),
+        Err(_) => buf.parse::<f64>().ok().map(Token::Float),
+    }
+}
+
+/// Converts the part of a hexadecimal numeral after the `0x` prefix.
+/// Hexadecimal floats have an optional fraction and an optional binary exponent,
+/// e.g. `0x1.8p3` is `1.5 * 2^3`.
+fn parse_hex_number(buf: &str, integers: bool) -> Option<Token<'static>> {
+    // The maximum number of significant digits to read. Further digits only scale the result.
+    const MAX_SIGNIFICANT_DIGITS: u32 = 30;
+
+    let (mantissa, exponent) = match buf.find(&['p', 'P'][..]) {
+        Some(idx) => (&buf[..idx], Some(&buf[idx + 1..])),
+        None => (buf, None),
+    };
+    let (int, frac) = match mantissa.find('.') {
+        Some(idx) => (&mantissa[..idx], Some(&mantissa[idx + 1..])),
+        None => (mantissa, None),
+    };
+    let digits = int.chars().chain(frac.unwrap_or("").chars());
+    if int.is_empty() && frac.is_none_or(str::is_empty) {
+        return None;
+    }
+    if !digits.clone().all(|chr| chr.is_ascii_hexdigit()) {
+        return None;
+    }
+    if integers && frac.is_none() && exponent.is_none() {
+        let num = digits.fold(0u64, |num, chr| {
+            num.wrapping_mul(16).wrapping_add(u64::from(chr.to_digit(16).unwrap_or(0)))
+        });
+        return Some(Token::Integer(num as i64));
+    }
+    let mut num = 0f64;
+    let mut exp = 0i32;
+    let mut significant_digits = 0;
+    for chr in int.chars() {
+        let digit = chr.to_digit(16).unwrap_or(0);
+        if significant_digits == 0 && digit == 0 {
+            continue;
+        }
+        significant_digits += 1;
+        if significant_digits <= MAX_SIGNIFICANT_DIGITS {
+            num = num * 16.0 + f64::from(digit);
+        } else {
+            exp = exp.saturating_add(4);
+        }
+    }
+    for chr in frac.unwrap_or("").chars() {
+        let digit = chr.to_digit(16).unwrap_or(0);
+        if significant_digits == 0 && digit == 0 {
+            exp = exp.saturating_sub(4);
+            continue;
+        }
+        significant_digits += 1;
+        if significant_digits <= MAX_SIGNIFICANT_DIGITS {
+            num = num * 16.0 + f64::from(digit);
+            exp = exp.saturating_sub(4);
+        }
+    }
+    if let Some(exponent) = exponent {
+        let (negative, digits) = match exponent.as_bytes().first() {
+            Some(&b'-') => (true, &exponent[1..]),
+            Some(&b'+') => (false, &exponent[1..]),
+            _ => (false, exponent),
+        };
+        if digits.is_empty() || !digits.bytes().all(|chr| chr.is_ascii_digit()) {
+            return None;
+        }
+        let value = digits.bytes().fold(0i32, |value, chr| {
+            value.saturating_mul(10).saturating_add(i32::from(chr - b'0'))
+        });
+        exp = exp.saturating_add(if negative { -value } else { value });
+    }
+    Some(Token::Float(scale_by_power_of_two(num, exp)))
+}
+
+/// Computes `num * 2^exp` without overflowing in intermediate steps, like C's `ldexp`.
+fn scale_by_power_of_two(mut num: f64, mut exp: i32) -> f64 {
+    while exp > 1000 {
+        num *= 2f64.powi(1000);
+        exp -= 1000;
+    }
+    while exp < -1000 {
+        num *= 2f64.powi(-1000);
+        exp += 1000;
+    }
+    num * 2f64.powi(exp)
+}
+
+/// Converts possibly borrowed bytes to text, replacing invalid UTF-8 sequences.
+/// Borrowed valid UTF-8 stays borrowed.
+fn to_text(buf: Cow<[u8]>) -> Cow<str> {
+    match buf {
+        Cow::Borrowed(buf) => String::from_utf8_lossy(buf),
+        Cow::Owned(buf) => match String::from_utf8(buf) {
+            Ok(buf) => Cow::Owned(buf),
+            Err(err) => Cow::Owned(String::from_utf8_lossy(err.as_bytes()).into_owned()),
+        },
+    }
+}
+
+/// Encodes a code point of up to 31 bits the way Lua does,
+/// using the original UTF-8 scheme of up to six bytes.
+fn encode_utf8_escape(mut value: u32, buf: &mut Vec<u8>) {
+    if value < 0x80 {
+        buf.push(value as u8);
+        return;
+    }
+    let mut tail = Vec::with_capacity(5);
+    // The maximum value that still fits into the first byte.
+    let mut max_first = 0x3Fu32;
+    loop {
+        tail.push(0x80 | (value & 0x3F) as u8);
+        value >>= 6;
+        max_first >>= 1;
+        if value <= max_first {
+            break;
+        }
+    }
+    buf.push(((!max_first << 1) | value) as u8);
+    buf.extend(tail.iter().rev());
+}
+
 /// Lexical analyser.
+/// Tokens borrow strings and comments from the source whenever they appear there
+/// verbatim, and identifiers are interned, so lexing does not allocate in the common case.
 #[derive(Debug, Clone)]
 pub struct Lexer<'a> {
-    /// The peekable buffer.
-    buf: Peekable<Chars<'a>>,
+    /// The source.
+    src: &'a [u8],
+    /// The dialect and extensions to accept.
+    options: Options,
     /// The current position.
     pos: TokenPosition,
+    /// The current byte offset, which is also the index of the next byte to read.
+    offset: usize,
+    /// The newline character that started the current line, if it was the last byte read.
+    /// Together with the other newline character it forms a single line break.
+    newline: Option<u8>,
+    /// Whether an error has been reported.
+    /// The lexer does not recover from errors, so the stream ends there.
+    failed: bool,
 }
 
 /// Implements `Lexer`.
 impl<'a> Lexer<'a> {
     /// Constructs a new `Lexer`.
-    pub fn new(src: &'a String) -> Lexer<'a> {
+    /// Lua source is a sequence of bytes, so anything that can be viewed
+    /// as a byte slice is accepted, e.g. `str`, `String` or `Vec<u8>`.
+    pub fn new<S: AsRef<[u8]> + ?Sized>(src: &'a S) -> Lexer<'a> {
+        Lexer::with_options(src, Options::default())
+    }
+    /// Constructs a new `Lexer` that accepts the given dialect and extensions.
+    pub fn with_options<S: AsRef<[u8]> + ?Sized>(src: &'a S, options: Options) -> Lexer<'a> {
+        Lexer::resume(src.as_ref(), options, 0, TokenPosition::default())
+    }
+    /// Constructs a new `Lexer` that starts at a byte offset with a known position,
+    /// which has to be the start of a token or whitespace.
+    pub(crate) fn resume(src: &'a [u8], options: Options, offset: usize, pos: TokenPosition) -> Lexer<'a> {
         Lexer {
-            buf: src.chars().peekable().to_owned(),
-            pos: TokenPosition::default(),
+            src,
+            options,
+            pos,
+            offset,
+            newline: None,
+            failed: false,
         }
     }
+    /// Gets the current byte offset.
+    pub(crate) fn offset(&self) -> usize {
+        self.offset
+    }
+    /// Gets the current position.
+    pub(crate) fn position(&self) -> TokenPosition {
+        self.pos
+    }
 }
 
 /// Implements `Iterator` for `Lexer`.
+/// Yields an error at most once, after which the stream ends.
 impl<'a> Iterator for Lexer<'a> {
-    type Item = Lexeme;
+    type Item = Result<Lexeme<'a>, LexError>;
 
     /// Reads the next `Item`.
-    fn next(&mut self) -> Option<Lexeme> {
+    fn next(&mut self) -> Option<Result<Lexeme<'a>, LexError>> {
+
+        // The source, which tokens may borrow from.
+        let src = self.src;
 
-        // The current position.
-        let now: TokenPosition;
+        // The dialect and extensions to accept.
+        let options = self.options;
 
         // Can be set if skipping a character after matching is not desired.
         let mut no_skip = false;
 
-        /// Logs a message.
-        macro_rules! log {
-            (INFO $msg:expr) => (println!(format!("{:?} {}", self.pos, String::from($msg))));
-            (ERR $msg:expr) => (panic!(format!("{:?} {}", self.pos, String::from($msg))));
+        /// Reports an error at the current position and ends the stream.
+        macro_rules! fail {
+            ($kind:expr) => {{
+                self.failed = true;
+                return Some(Err(LexError {
+                    kind: $kind,
+                    pos: self.pos,
+                }));
+            }};
         }
 
-        /// Peeks at a character in the stream.
-        /// Currently, peeking at the next character (n=0) is way faster,
-        /// because the buffer doesn't have to be cloned for that.
-        macro_rules! peek {
+        if self.failed {
+            return None;
+        }
 
-            // Simple case, just peeks at the current character.
-            () => {
-                match self.buf.peek() {
-                    Some(chr) => Some(chr.to_owned()),
-                    _ => None,
+        /// Reports an error unless the selected dialect is at least the given one.
+        macro_rules! require {
+            ($dialect:expr, $construct:expr) => {
+                if options.dialect < $dialect {
+                    fail!(LexErrorKind::Unsupported($construct, options.dialect));
                 }
             };
+        }
 
-            // Unfortunate case, peeks at a character more than 0 steps away.
-            // Currently very expensive, optimizes to the simple case if n == 0.
-            ($n:expr) => {{
-                let n = $n as usize;
-                if n == 0 {
-                    peek!()
-                } else {
-                    // TODO:
-                    // Find a solution to the buffer-cloning.
-                    // This is supposed to be fast!
-                    match self.buf.clone().skip(n).next() {
-                        Some(chr) => Some(chr.to_owned()),
-                        _ => None,
-                    }
-                }
-            }};
+        /// Peeks at a byte in the stream, `n` bytes ahead of the current one.
+        /// The source is indexed directly, so this is constant-time for any `n`.
+        macro_rules! peek {
+            () => (src.get(self.offset).cloned());
+            ($n:expr) => (src.get(self.offset + ($n as usize)).cloned());
         }
 
-        /// Skips a specific amount of characters.
+        /// Skips a specific amount of bytes.
         /// This macro is to be used for the purpose of advancing the stream.
         /// It keeps track of the current line and the cursor position on the current line.
+        /// UTF-8 continuation bytes do not advance the cursor position.
         macro_rules! skip {
             ($n:expr) => {
                 for _ in 0..($n as usize) {
-                    let chr: Option<char> = match peek!() {
-                        Some(chr) => Some(chr.to_owned()),
-                        _ => None,
-                    };
-                    if chr.is_some() {
-                        let chr = chr.unwrap();
+                    if let Some(&chr) = src.get(self.offset) {
+                        self.offset += 1;
+                        let newline = self.newline.take();
                         match chr {
-                            '\n' => {
+                            // `\r\n` and `\n\r` are a single line break, like in `skip_newline!`.
+                            b'\n' | b'\r' if newline.is_some_and(|prev| prev != chr) => (),
+                            b'\n' | b'\r' => {
                                 self.pos.line += 1;
                                 self.pos.pos = 0;
+                                self.newline = Some(chr);
                             }
+                            _ if chr & 0xC0 == 0x80 => (),
                             _ => self.pos.pos += 1,
                         };
-                        self.buf.next();
                     }
                 }
             };
@@ -132,19 +467,9 @@ impl<'a> Iterator for Lexer<'a> {
 
         /// Scans an operator based on the next character in the stream.
         macro_rules! scan_op {
-            ($expected:expr, $tk:expr) => {
-                match peek!(1) {
-                    Some(chr) if chr == ($expected as char) => {
-                        skip!(1);
-                        ($tk as Token)
-                    }
-                    Some(other) => log!(ERR format!("Unimplemented operator: `{}`", other)),
-                    None => log!(ERR "Unexpected end of stream."),
-                }
-            };
             ($expected:expr, $tka:expr, $tkb:expr) => {
                 match peek!(1) {
-                    Some(chr) if chr == ($expected as char) => {
+                    Some(chr) if chr == $expected => {
                         skip!(1);
                         ($tka as Token)
                     }
@@ -156,156 +481,516 @@ impl<'a> Iterator for Lexer<'a> {
         /// Advances the stream as long as the current character is considered whitespace.
         macro_rules! skip_whitespace {
             () => {
-                while peek!().is_some() && peek!().unwrap().is_whitespace() {
+                while let Some(chr) = peek!() {
+                    if !is_whitespace(chr) {
+                        break;
+                    }
                     skip!(1);
                 }
             }
         }
 
         /// Reads a line from the character stream.
+        /// Leading whitespace is skipped, but the line never extends past a newline (`\n` or `\r`).
         macro_rules! read_line {
             () => {{
-                skip_whitespace!();
-                let mut comment = String::new();
+                while let Some(chr) = peek!() {
+                    if chr == b'\n' || chr == b'\r' || !is_whitespace(chr) {
+                        break;
+                    }
+                    skip!(1);
+                }
+                let start = self.offset;
+                while let Some(chr) = peek!() {
+                    if chr == b'\n' || chr == b'\r' {
+                        break;
+                    }
+                    skip!(1);
+                }
+                // The newline is not part of the line.
+                no_skip = true;
+                String::from_utf8_lossy(&src[start..self.offset])
+            }};
+        }
+
+        /// Skips a single newline sequence (`\n`, `\r`, `\r\n` or `\n\r`), if any.
+        macro_rules! skip_newline {
+            () => {
+                match peek!() {
+                    Some(chr) if chr == b'\n' || chr == b'\r' => {
+                        skip!(1);
+                        match peek!() {
+                            Some(next) if (next == b'\n' || next == b'\r') && next != chr => skip!(1),
+                            _ => (),
+                        }
+                    }
+                    _ => (),
+                }
+            };
+        }
+
+        /// Determines the level of the long bracket starting at the current character,
+        /// i.e. the number of `=` signs following it, and whether the bracket is
+        /// completed by the expected character.
+        macro_rules! bracket_level {
+            ($close:expr) => {{
+                let mut level = 0usize;
+                while peek!(level + 1) == Some(b'=') {
+                    level += 1;
+                }
+                (level, peek!(level + 1) == Some($close))
+            }};
+        }
+
+        /// Reads the contents of a long bracket, e.g. `[==[ ... ]==]`.
+        /// Expects the stream to be at the opening bracket and stops at the last
+        /// character of the closing bracket. A newline directly following the
+        /// opening bracket is not part of the contents.
+        /// The contents are borrowed from the source unless they contain newline
+        /// sequences other than `\n`, which are normalized.
+        macro_rules! read_long_bracket {
+            ($level:expr, $unterminated:expr) => {{
+                let level = $level;
+                skip!(level + 2);
+                skip_newline!();
+                let start = self.offset;
+                let mut buf: Option<Vec<u8>> = None;
                 loop {
-                    match self.buf.peek().cloned() {
-                        Some('\n') | None => break,
+                    match peek!() {
+                        Some(b']') if bracket_level!(b']') == (level, true) => break,
+                        Some(chr) if chr == b'\r' || (chr == b'\n' && peek!(1) == Some(b'\r')) => {
+                            let end = self.offset;
+                            buf.get_or_insert_with(|| src[start..end].to_vec()).push(b'\n');
+                            skip_newline!();
+                        }
                         Some(chr) => {
                             skip!(1);
-                            comment.push(chr)
+                            if let Some(ref mut buf) = buf {
+                                buf.push(chr);
+                            }
                         }
+                        None => fail!($unterminated),
                     }
                 }
-                comment
+                let end = self.offset;
+                skip!(level + 1);
+                match buf {
+                    Some(buf) => Cow::Owned(buf),
+                    None => Cow::Borrowed(&src[start..end]),
+                }
             }};
         }
 
-        /// Creates a (Token, TokenPosition) tuple.
+        // Skip whitespace.
+        skip_whitespace!();
+
+        // The position at which the current token starts.
+        let now = self.pos;
+        let start = self.offset;
+
+        /// Emits a token. The lexeme is completed once the token has been skipped.
         macro_rules! emit {
             ($token:expr)
-            => (Some(Lexeme($token as Token, now)));
-            ($token:expr, $pos:expr)
-            => (Some(Lexeme($token as Token, $pos as TokenPosition)));
+            => ($token as Token);
         }
 
-        /// Creates a (Token, TokenPosition) tuple using scan_op and emit.
+        /// Emits a token using scan_op.
         macro_rules! emitx {
-            ($expected:expr, $tk:expr)
-            => (emit!(scan_op!(($expected as char), ($tk as Token))));
             ($expected:expr, $tka:expr, $tkb:expr)
-            => (emit!(scan_op!(($expected as char), ($tka as Token), ($tkb as Token))));
+            => (emit!(scan_op!($expected, ($tka as Token), ($tkb as Token))));
         }
 
-        // Skip whitespace.
-        skip_whitespace!();
+        /// Emits a compound assignment token if the next character is `=` and
+        /// the extension is enabled, or the plain operator otherwise.
+        macro_rules! emit_compound {
+            ($tka:expr, $tkb:expr) => {
+                if options.compound_assignment {
+                    emitx!(b'=', $tkb, $tka)
+                } else {
+                    emit!($tka)
+                }
+            };
+        }
 
-        // Update the current position.
-        now = self.pos;
+        /// Reads a numeral and converts it to a number token.
+        /// Like the reference implementation, this reads greedily and leaves the
+        /// validation to the conversion, so that e.g. `3x` or `1..2` are reported
+        /// as malformed numbers instead of being split into several tokens.
+        macro_rules! read_number {
+            () => {{
+                let mut exponent = (b'e', b'E');
+                if peek!() == Some(b'0') && (peek!(1) == Some(b'x') || peek!(1) == Some(b'X')) {
+                    exponent = (b'p', b'P');
+                    skip!(2);
+                }
+                loop {
+                    match peek!() {
+                        Some(chr) if chr == exponent.0 || chr == exponent.1 => {
+                            skip!(1);
+                            if let Some(b'+') | Some(b'-') = peek!() {
+                                skip!(1);
+                            }
+                        }
+                        Some(chr) if chr.is_ascii_hexdigit() || chr == b'.' => skip!(1),
+                        _ => break,
+                    }
+                }
+                // A numeral touching a letter is malformed.
+                if let Some(chr) = peek!() {
+                    if chr.is_ascii_alphabetic() || chr == b'_' {
+                        skip!(1);
+                    }
+                }
+                no_skip = true;
+                // Everything read so far is ASCII.
+                let buf = String::from_utf8_lossy(&src[start..self.offset]);
+                if exponent.0 == b'p' && buf.contains(&['.', 'p', 'P'][..]) {
+                    require!(Dialect::Lua52, "hexadecimal float");
+                }
+                match parse_number(&buf, options.dialect >= Dialect::Lua53) {
+                    Some(token) => emit!(token),
+                    None => fail!(LexErrorKind::MalformedNumber(buf.into_owned())),
+                }
+            }};
+        }
 
         // The actual lexical analysis is done here.
         if let Some(chr) = peek!() {
             let result = match chr {
-                '(' => emit!(Token::OpenParen),
-                ')' => emit!(Token::CloseParen),
-                '[' => emit!(Token::OpenBracket),
-                ']' => emit!(Token::CloseBracket),
-                '{' => emit!(Token::OpenBrace),
-                '}' => emit!(Token::CloseBrace),
-                '|' => emit!(Token::Lambda),
-                ',' => emit!(Token::Comma),
-                ';' => emit!(Token::Semicolon),
-                '+' => emit!(Token::Add),
-                '*' => emit!(Token::Mul),
-                '/' => emit!(Token::Div),
-                '%' => emit!(Token::Mod),
-                '^' => emit!(Token::Power),
-                '$' => emit!(Token::Dollar),
-                '~' => emitx!('=', Token::NotEqual),
-                '=' => emitx!('=', Token::Equal, Token::Assignment),
-                '<' => emitx!('=', Token::LessThanEqual, Token::LessThan),
-                '>' => emitx!('=', Token::GreaterThanEqual, Token::GreaterThan),
-                ':' => emitx!(':', Token::DoubleColon, Token::Colon),
-                '.' => {
+                b'(' => emit!(Token::OpenParen),
+                b')' => emit!(Token::CloseParen),
+                b'[' => {
+                    match bracket_level!(b'[') {
+                        (level, true) => {
+                            let buf = read_long_bracket!(level, LexErrorKind::UnterminatedString);
+                            emit!(Token::StaticString(buf))
+                        }
+                        (0, false) => emit!(Token::OpenBracket),
+                        (_, false) => fail!(LexErrorKind::InvalidLongDelimiter),
+                    }
+                }
+                b']' => emit!(Token::CloseBracket),
+                b'{' => emit!(Token::OpenBrace),
+                b'}' => emit!(Token::CloseBrace),
+                b'|' => {
+                    // Lambdas use `|` in every dialect.
+                    if !options.lambda {
+                        require!(Dialect::Lua53, "`|`");
+                    }
+                    emit_compound!(Token::BitOr, Token::BitOrAssign)
+                }
+                b'&' => {
+                    require!(Dialect::Lua53, "`&`");
+                    emit_compound!(Token::BitAnd, Token::BitAndAssign)
+                }
+                b',' => emit!(Token::Comma),
+                b';' => emit!(Token::Semicolon),
+                b'+' => emit_compound!(Token::Add, Token::AddAssign),
+                b'*' => emit_compound!(Token::Mul, Token::MulAssign),
+                b'/' => {
+                    match peek!(1) {
+                        Some(b'/') => {
+                            require!(Dialect::Lua53, "`//`");
+                            skip!(1);
+                            emit_compound!(Token::FloorDiv, Token::FloorDivAssign)
+                        }
+                        _ => emit_compound!(Token::Div, Token::DivAssign),
+                    }
+                }
+                b'%' => emit_compound!(Token::Mod, Token::ModAssign),
+                b'^' => emit_compound!(Token::Power, Token::PowerAssign),
+                b'~' => {
+                    if peek!(1) != Some(b'=') {
+                        require!(Dialect::Lua53, "`~`");
+                    }
+                    emitx!(b'=', Token::NotEqual, Token::BitXorOrNot)
+                }
+                b'=' => emitx!(b'=', Token::Equal, Token::Assignment),
+                b'<' => {
+                    match peek!(1) {
+                        Some(b'<') => {
+                            require!(Dialect::Lua53, "`<<`");
+                            skip!(1);
+                            emit_compound!(Token::ShiftLeft, Token::ShiftLeftAssign)
+                        }
+                        _ => emitx!(b'=', Token::LessThanEqual, Token::LessThan),
+                    }
+                }
+                b'>' => {
                     match peek!(1) {
-                        Some('.') => {
+                        Some(b'>') => {
+                            require!(Dialect::Lua53, "`>>`");
                             skip!(1);
-                            emitx!('.', Token::VarArgs, Token::Concat)
+                            emit_compound!(Token::ShiftRight, Token::ShiftRightAssign)
+                        }
+                        _ => emitx!(b'=', Token::GreaterThanEqual, Token::GreaterThan),
+                    }
+                }
+                b':' => {
+                    if peek!(1) == Some(b':') {
+                        require!(Dialect::Lua52, "`::`");
+                    }
+                    emitx!(b':', Token::DoubleColon, Token::Colon)
+                }
+                b'0'..=b'9' => read_number!(),
+                b'.' if peek!(1).is_some_and(|chr| chr.is_ascii_digit()) => read_number!(),
+                b'.' => {
+                    match peek!(1) {
+                        Some(b'.') => {
+                            skip!(1);
+                            match peek!(1) {
+                                Some(b'.') => {
+                                    skip!(1);
+                                    emit!(Token::VarArgs)
+                                }
+                                _ => emit_compound!(Token::Concat, Token::ConcatAssign),
+                            }
                         }
                         _ => emit!(Token::MemberAccess),
                     }
                 }
-                '#' => {
+                b'#' => {
                     match peek!(1) {
-                        Some('!') if now.line == 1 && now.pos == 0 => {
+                        Some(b'!') if now.line == 1 && now.pos == 0 => {
                             skip!(2);
                             let line = read_line!();
                             emit!(Token::Hashbang(line))
                         }
-                        Some('!') => {
-                            log!(ERR "The shebang has to be on the first line!");
-                        }
+                        Some(b'!') => fail!(LexErrorKind::MisplacedHashbang),
                         Some(_) | None => emit!(Token::Len),
                     }
                 }
-                '-' => {
+                b'-' => {
                     match peek!(1) {
-                        Some('-') => {
+                        Some(b'-') => {
                             skip!(2);
-                            let line = read_line!();
-                            emit!(Token::Comment(line))
+                            match peek!() {
+                                Some(b'[') => {
+                                    match bracket_level!(b'[') {
+                                        (level, true) => {
+                                            let buf = read_long_bracket!(level, LexErrorKind::UnterminatedComment);
+                                            emit!(Token::Comment(to_text(buf)))
+                                        }
+                                        _ => emit!(Token::Comment(read_line!())),
+                                    }
+                                }
+                                _ => emit!(Token::Comment(read_line!())),
+                            }
                         }
-                        Some(_) | None => emit!(Token::SubOrMinus),
+                        Some(_) | None => emit_compound!(Token::SubOrMinus, Token::SubAssign),
                     }
                 }
-                '"' | '\'' => {
-                    let mut buf = String::new();
-                    let delimiter = peek!().unwrap();
+                b'$' if !options.interpolation => {
+                    fail!(LexErrorKind::Unsupported("string interpolation", options.dialect))
+                }
+                b'$' if peek!(1) != Some(b'"') && peek!(1) != Some(b'\'') => {
+                    fail!(LexErrorKind::UnexpectedCharacter('$'))
+                }
+                b'"' | b'\'' | b'$' => {
+                    // An interpolated string, e.g. `$"a = {a}"`, embeds expressions in braces.
+                    // Literal braces are written as `{{` and `}}`.
+                    let interpolated = chr == b'$';
+                    if interpolated {
+                        skip!(1);
+                    }
+                    let delimiter = peek!().unwrap_or(chr);
                     skip!(1);
-                    while let Some(chr) = peek!() {
+                    let mut content_start = self.offset;
+                    let mut fragments = Vec::new();
+
+                    // The contents are borrowed from the source until the first escape sequence,
+                    // after which they have to be copied.
+                    let mut buf: Option<Vec<u8>> = None;
+
+                    /// Gets the copied contents, copying everything read so far if necessary.
+                    macro_rules! owned {
+                        () => {{
+                            let end = self.offset;
+                            buf.get_or_insert_with(|| src[content_start..end].to_vec())
+                        }};
+                    }
+
+                    loop {
+                        let chr = match peek!() {
+                            Some(chr) => chr,
+                            None => fail!(LexErrorKind::UnterminatedString),
+                        };
                         match chr {
-                            '\\' => {
+                            b'\\' => {
+                                owned!();
                                 skip!(1);
-                                if let Some(chr) = peek!() {
-                                    skip!(1);
-                                    buf.push(match chr {
-                                        '\\' => '\\',
-                                        '\'' => '\'',
-                                        '"' => '"',
-                                        'a' => '\x07',
-                                        'b' => '\x08',
-                                        'v' => '\x0b',
-                                        'f' => '\x0c',
-                                        'n' => '\n',
-                                        'r' => '\r',
-                                        't' => '\t',
-                                        '[' => '[',
-                                        ']' => ']',
-                                        _ => log!(ERR format!("Invalid escape code: `\\{}`", chr)),
-                                    });
-                                } else {
-                                    log!(ERR "Unexpected end of string.")
+                                match peek!() {
+                                    // A backslash followed by a newline continues the string
+                                    // on the next line, keeping the newline.
+                                    Some(chr) if chr == b'\n' || chr == b'\r' => {
+                                        skip_newline!();
+                                        owned!().push(b'\n');
+                                    }
+                                    // `\z` skips all following whitespace, including newlines.
+                                    Some(b'z') => {
+                                        require!(Dialect::Lua52, "`\\z`");
+                                        skip!(1);
+                                        skip_whitespace!();
+                                    }
+                                    // `\xXX`, exactly two hexadecimal digits.
+                                    Some(b'x') => {
+                                        require!(Dialect::Lua52, "`\\x`");
+                                        skip!(1);
+                                        let mut value = 0u32;
+                                        for _ in 0..2 {
+                                            match peek!().and_then(|chr| (chr as char).to_digit(16)) {
+                                                Some(digit) => {
+                                                    skip!(1);
+                                                    value = value * 16 + digit;
+                                                }
+                                                None => fail!(LexErrorKind::InvalidHexEscape),
+                                            }
+                                        }
+                                        owned!().push(value as u8);
+                                    }
+                                    // `\u{XXX}`, a code point of up to 31 bits.
+                                    Some(b'u') => {
+                                        require!(Dialect::Lua53, "`\\u`");
+                                        skip!(1);
+                                        if peek!() != Some(b'{') {
+                                            fail!(LexErrorKind::MalformedUnicodeEscape);
+                                        }
+                                        skip!(1);
+                                        let mut value = 0u32;
+                                        let mut digits = 0;
+                                        loop {
+                                            match peek!() {
+                                                Some(b'}') if digits > 0 => break,
+                                                Some(chr) if chr.is_ascii_hexdigit() => {
+                                                    if value > 0x7FF_FFFF {
+                                                        fail!(LexErrorKind::UnicodeEscapeTooLarge);
+                                                    }
+                                                    skip!(1);
+                                                    value = value * 16 + (chr as char).to_digit(16).unwrap_or(0);
+                                                    digits += 1;
+                                                }
+                                                _ => fail!(LexErrorKind::MalformedUnicodeEscape),
+                                            }
+                                        }
+                                        skip!(1);
+                                        encode_utf8_escape(value, owned!());
+                                    }
+                                    // `\ddd`, up to three decimal digits.
+                                    Some(chr) if chr.is_ascii_digit() => {
+                                        let mut value = 0u32;
+                                        for _ in 0..3 {
+                                            match peek!().and_then(|chr| (chr as char).to_digit(10)) {
+                                                Some(digit) => {
+                                                    skip!(1);
+                                                    value = value * 10 + digit;
+                                                }
+                                                None => break,
+                                            }
+                                        }
+                                        if value > 0xFF {
+                                            fail!(LexErrorKind::DecimalEscapeTooLarge);
+                                        }
+                                        owned!().push(value as u8);
+                                    }
+                                    Some(chr) => {
+                                        skip!(1);
+                                        owned!().push(match chr {
+                                            b'\\' => b'\\',
+                                            b'\'' => b'\'',
+                                            b'"' => b'"',
+                                            b'a' => b'\x07',
+                                            b'b' => b'\x08',
+                                            b'v' => b'\x0b',
+                                            b'f' => b'\x0c',
+                                            b'n' => b'\n',
+                                            b'r' => b'\r',
+                                            b't' => b'\t',
+                                            b'[' => b'[',
+                                            b']' => b']',
+                                            _ => fail!(LexErrorKind::InvalidEscape(char::from(chr))),
+                                        });
+                                    }
+                                    None => fail!(LexErrorKind::UnterminatedString),
                                 }
                             }
-                            _ if chr == delimiter => {
+                            _ if chr == delimiter => break,
+                            b'\n' | b'\r' => fail!(LexErrorKind::UnterminatedString),
+                            b'{' | b'}' if interpolated && peek!(1) == Some(chr) => {
+                                owned!().push(chr);
+                                skip!(2);
+                            }
+                            b'}' if interpolated => fail!(LexErrorKind::UnexpectedCharacter('}')),
+                            b'{' if interpolated => {
+                                let text = match buf.take() {
+                                    Some(buf) => Cow::Owned(buf),
+                                    None => Cow::Borrowed(&src[content_start..self.offset]),
+                                };
+                                if !text.is_empty() {
+                                    fragments.push(Fragment::Text(text));
+                                }
                                 skip!(1);
-                                break;
+                                // The expression is lexed up to the matching closing brace.
+                                let mut lexer = Lexer::resume(src, options, self.offset, self.pos);
+                                let mut tokens = Vec::new();
+                                let mut depth = 0usize;
+                                loop {
+                                    match lexer.next() {
+                                        Some(Ok(Lexeme(Token::CloseBrace, ..))) if depth == 0 => break,
+                                        Some(Ok(lexeme)) => {
+                                            match lexeme.0 {
+                                                Token::OpenBrace => depth += 1,
+                                                Token::CloseBrace => depth -= 1,
+                                                _ => (),
+                                            }
+                                            tokens.push(lexeme);
+                                        }
+                                        // The offset is kept where the nested lexer stopped, so that
+                                        // the stream lexer can tell whether it ran out of input.
+                                        Some(Err(err)) => {
+                                            self.offset = lexer.offset;
+                                            self.failed = true;
+                                            return Some(Err(err));
+                                        }
+                                        None => {
+                                            self.offset = lexer.offset;
+                                            fail!(LexErrorKind::UnterminatedString)
+                                        }
+                                    }
+                                }
+                                self.offset = lexer.offset;
+                                self.pos = lexer.pos;
+                                fragments.push(Fragment::Expr(tokens));
+                                content_start = self.offset;
                             }
                             _ => {
                                 skip!(1);
-                                buf.push(chr);
+                                if let Some(ref mut buf) = buf {
+                                    buf.push(chr);
+                                }
                             }
                         }
                     }
-                    emit!(Token::StaticString(buf))
+                    let text = match buf {
+                        Some(buf) => Cow::Owned(buf),
+                        None => Cow::Borrowed(&src[content_start..self.offset]),
+                    };
+                    if !interpolated {
+                        emit!(Token::StaticString(text))
+                    } else {
+                        if !text.is_empty() {
+                            fragments.push(Fragment::Text(text));
+                        }
+                        emit!(Token::Interpolation(fragments))
+                    }
                 }
                 chr => {
-                    if chr.is_alphabetic() || chr == '_' {
-                        let mut buf = String::new();
+                    if is_ident(chr) && !chr.is_ascii_digit() {
+                        let mut is_ascii = true;
                         loop {
                             match peek!() {
-                                Some(chr) if chr.is_alphanumeric() || chr == '_' => {
+                                Some(chr) if is_ident(chr) => {
+                                    is_ascii &= chr.is_ascii();
                                     skip!(1);
-                                    buf.push(chr);
                                 }
                                 Some(_) | None => {
                                     no_skip = true;
@@ -313,7 +998,26 @@ impl<'a> Iterator for Lexer<'a> {
                                 }
                             }
                         }
-                        match buf.as_ref() {
+                        let buf = match str::from_utf8(&src[start..self.offset]) {
+                            Ok(buf) => buf,
+                            Err(_) => fail!(LexErrorKind::InvalidUtf8),
+                        };
+                        // Identifiers may contain any alphabetic Unicode character,
+                        // so non-ASCII characters have to be checked here.
+                        if !is_ascii {
+                            if options.ascii_identifiers {
+                                if let Some(chr) = buf.chars().find(|chr| !chr.is_ascii()) {
+                                    fail!(LexErrorKind::UnexpectedCharacter(chr));
+                                }
+                            }
+                            if let Some(chr) = buf.chars().find(|&chr| !(chr.is_alphanumeric() || chr == '_')) {
+                                fail!(LexErrorKind::UnexpectedCharacter(chr));
+                            }
+                            if let Some(chr) = buf.chars().next().filter(|chr| chr.is_numeric()) {
+                                fail!(LexErrorKind::UnexpectedCharacter(chr));
+                            }
+                        }
+                        match buf {
                             "and" => emit!(Token::Keyword(Keyword::And)),
                             "break" => emit!(Token::Keyword(Keyword::Break)),
                             "do" => emit!(Token::Keyword(Keyword::Do)),
@@ -323,7 +1027,7 @@ impl<'a> Iterator for Lexer<'a> {
                             "false" => emit!(Token::Keyword(Keyword::False)),
                             "for" => emit!(Token::Keyword(Keyword::For)),
                             "function" => emit!(Token::Keyword(Keyword::Function)),
-                            "goto" => emit!(Token::Keyword(Keyword::Goto)),
+                            "goto" if options.dialect >= Dialect::Lua52 => emit!(Token::Keyword(Keyword::Goto)),
                             "if" => emit!(Token::Keyword(Keyword::If)),
                             "in" => emit!(Token::Keyword(Keyword::In)),
                             "local" => emit!(Token::Keyword(Keyword::Local)),
@@ -336,111 +1040,17 @@ impl<'a> Iterator for Lexer<'a> {
                             "true" => emit!(Token::Keyword(Keyword::True)),
                             "until" => emit!(Token::Keyword(Keyword::Until)),
                             "while" => emit!(Token::Keyword(Keyword::While)),
-                            _ => emit!(Token::Ident(buf)),
-                        }
-                    } else if chr.is_digit(10) {
-                        // The following is some EBNF I found online.
-                        //
-                        // INT: Digit+
-                        // HEX: '0' [xX] HexDigit+
-                        // FLOAT: Digit+ '.' Digit* ExponentPart?
-                        // 		| '.' Digit+ ExponentPart?
-                        // 		| Digit+ ExponentPart
-                        // ExponentPart: [eE] [+-]? Digit+
-                        //
-                        // Important implementation details:
-                        // - Hexadecimal floats are completely ignored here.
-                        // - Hexadecimal exponents are also ignored.
-                        // Reason: No sane person would ever use those.
-                        // PS: Hexadecimal exponents may actually be useful.
-                        // PSS: Hexadecimal floats though.. just don't.
-                        let mut buf = String::new();
-                        let mut has_exponent = false;
-                        let mut has_fractional = false;
-                        let is_hexadecimal = {
-                            match peek!(1) {
-                                Some(chr) if vec!['x', 'X'].contains(&chr) => {
-                                    if peek!().unwrap_or(' ') != '0' {
-                                        false
-                                    } else {
-                                        match peek!(2) {
-                                            Some(chr) if !chr.is_digit(16) => {
-                                                log!(ERR format!("Unexpected character in hexnum: `{}`", chr))
-                                            }
-                                            None => log!(ERR "Unexpected end of hexnum."),
-                                            Some(_) => (),
-                                        }
-                                        skip!(2);
-                                        true
-                                    }
-                                }
-                                _ => false,
-                            }
-                        };
-                        macro_rules! is_num {
-                            ($chr:expr) => {{
-                                let chr = $chr as char;
-                                if has_exponent && !chr.is_digit(10) {
-                                    log!(ERR format!("Unexpected character in exponent: `{}`", chr));
-                                }
-                                (is_hexadecimal && chr.is_digit(16)) ||
-                                (!is_hexadecimal && chr.is_digit(10))
-                            }};
-                        }
-                        loop {
-                            match peek!() {
-                                Some(chr) if chr == '.' => {
-                                    if has_fractional {
-                                        log!(ERR "A number can contain one fractional part at max!");
-                                    } else {
-                                        has_fractional = true;
-                                        skip!(1);
-                                        buf.push(chr);
-                                    }
-                                }
-                                Some(chr) if is_num!(chr.to_owned()) => {
-                                    skip!(1);
-                                    buf.push(chr);
-                                }
-                                Some(chr) if vec!['e', 'E'].contains(&chr) => {
-                                    if has_exponent {
-                                        log!(ERR "A number can contain one exponent at max!");
-                                    } else {
-                                        has_exponent = true;
-                                        skip!(1);
-                                        buf.push(chr);
-                                        match peek!() {
-                                            Some('-') => {
-                                                skip!(1);
-                                                buf.push(chr);
-                                            }
-                                            Some(_) | None => (),
-                                        }
-                                    }
-                                }
-                                Some(_) | None => break,
-                            }
-                        }
-                        if is_hexadecimal {
-                            match i64::from_str_radix(buf.as_str(), 16) {
-                                Ok(num) => emit!(Token::Number(num as f64)),
-                                Err(msg) => log!(ERR format!("{:?}", msg)),
-                            }
-                        } else {
-                            match buf.parse::<f64>() {
-                                Ok(num) => emit!(Token::Number(num)),
-                                Err(_) => log!(ERR format!("The number `{}` is malformed and doesn't parse.", buf)),
-                            }
+                            _ => emit!(Token::Ident(Symbol::intern(buf))),
                         }
                     } else {
-                        log!(ERR format!("Unimplemented operator: `{}`", chr))
+                        fail!(LexErrorKind::UnexpectedCharacter(char::from(chr)))
                     }
                 }
             };
             if !no_skip {
                 skip!(1);
             }
-            result
+            Some(Ok(Lexeme(result, now, Span { start, end: self.offset })))
         } else {
             None
         }
@@ -3,42 +3,89 @@
 
 //! The Lua library.
 //! Contains the full lexer, parser, vm and runtime.
+//! With the `serde` feature, tokens and the AST can be serialized, see `serialize`.
+
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
 // Lexer
-mod token;
+pub mod dialect;
+pub mod intern;
+pub mod token;
 pub mod lexer;
+pub mod trivia;
+pub mod incremental;
+pub mod stream;
+
+// Runtime
+mod datatype;
+pub mod lua;
 
 // Parser
 pub mod parser;
+pub mod visit;
+pub mod format;
+#[cfg(feature = "serde")]
+pub mod serialize;
 
 #[cfg(test)]
 mod tests {
-    use lexer::{Lexer, Lexeme};
-    use token::{Token, Keyword};
+    use dialect::{Dialect, Options};
+    use intern::Symbol;
+    use lexer::{Lexer, Lexeme, LexErrorKind, Span};
+    use trivia::{LosslessLexer, Trivia, TriviaKind};
+    use incremental::{relex, TextEdit};
+    use stream::StreamLexer;
+    use std::io::{self, Read};
+    use token::{to_source, Fragment, Token, Keyword};
+    use parser::{Parser, ParseError, ParseErrorKind};
+    use parser::ast::*;
+    use visit::{self, Visitor, VisitorMut};
+    use format::{format, FormatError, FormatOptions, Indent, QuoteStyle};
+    use lua::{ArithmeticOp, BitwiseOp, LogicalOp, LuaOperation};
+    use std::borrow::Cow;
     use std::iter::Iterator;
     macro_rules! matchseq {
         ($lex:expr$(,$a:expr)*) => {{
-            let mut lex = &mut $lex as &mut Lexer;
+            let lex = &mut $lex as &mut Lexer;
             $({
                 match Iterator::next(lex) {
-                    Some(Lexeme(tk, _)) => assert_eq!(tk, Token::from($a)),
+                    Some(Ok(Lexeme(tk, ..))) => assert_eq!(tk, Token::from($a)),
+                    Some(Err(err)) => panic!("{}", err),
                     None => unimplemented!(),
                 };
             })* {
-                while Iterator::next(lex).is_some() {}
+                for res in lex {
+                    if let Err(err) = res {
+                        panic!("{}", err);
+                    }
+                }
+            }
+        }};
+    }
+    macro_rules! matcherr {
+        ($lex:expr, $kind:expr) => {{
+            let lex = &mut $lex as &mut Lexer;
+            match lex.find(|res| res.is_err()) {
+                Some(Err(err)) => assert_eq!(err.kind, $kind),
+                _ => panic!("expected an error"),
             }
+            assert!(lex.next().is_none());
         }};
     }
     #[test]
     fn lex_op_dot() {
-        let src = format!(". .. ...");
-        let mut lex = Lexer::new(&src);
+        let src = ". .. ...";
+        let mut lex = Lexer::new(src);
         matchseq!(lex, Token::MemberAccess, Token::Concat, Token::VarArgs);
     }
     #[test]
     fn lex_op_comp() {
-        let src = format!("< <= > >= == ~=");
-        let mut lex = Lexer::new(&src);
+        let src = "< <= > >= == ~=";
+        let mut lex = Lexer::new(src);
         matchseq!(lex,
                   Token::LessThan,
                   Token::LessThanEqual,
@@ -49,88 +96,1175 @@ mod tests {
     }
     #[test]
     fn lex_comment() {
-        let src = format!("\n-- hello, world!\n");
-        let mut lex = Lexer::new(&src);
-        matchseq!(lex, Token::Comment(format!("hello, world!")));
+        let src = "\n-- hello, world!\n";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex, Token::Comment("hello, world!".into()));
     }
     #[test]
     fn lex_hashbang() {
-        let src = format!("#!/usr/bin/env lua\n");
-        let mut lex = Lexer::new(&src);
-        matchseq!(lex, Token::Hashbang(format!("/usr/bin/env lua")));
+        let src = "#!/usr/bin/env lua\n";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex, Token::Hashbang("/usr/bin/env lua".into()));
     }
     #[test]
-    #[should_panic]
     fn lex_hashbang_invalid() {
-        let src = format!("\n#!/usr/bin/env lua\n");
-        let mut lex = Lexer::new(&src);
-        matchseq!(lex);
+        let src = "\n#!/usr/bin/env lua\n";
+        let mut lex = Lexer::new(src);
+        matcherr!(lex, LexErrorKind::MisplacedHashbang);
     }
     #[test]
     fn lex_ident() {
-        let src = format!("hello world");
-        let mut lex = Lexer::new(&src);
+        let src = "hello world";
+        let mut lex = Lexer::new(src);
         matchseq!(lex, "hello", "world");
     }
     #[test]
     fn lex_num_dec_int() {
-        let src = format!("1234");
-        let mut lex = Lexer::new(&src);
-        matchseq!(lex, Token::Number(1234f64));
+        let src = "1234";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex, Token::Integer(1234));
     }
     #[test]
     fn lex_num_dec_int_exp() {
-        let src = format!("1234E31");
-        let mut lex = Lexer::new(&src);
-        matchseq!(lex, Token::Number(1234E31f64));
+        let src = "1234E31";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex, Token::Float(1234E31f64));
     }
     #[test]
-    #[should_panic]
     fn lex_num_dec_int_invalid_exp() {
-        let src = format!("1234EFF");
-        let mut lex = Lexer::new(&src);
-        matchseq!(lex);
+        let src = "1234EFF";
+        let mut lex = Lexer::new(src);
+        matcherr!(lex, LexErrorKind::MalformedNumber("1234EFF".to_string()));
     }
     #[test]
     fn lex_num_dec_float() {
-        let src = format!("1.234");
-        let mut lex = Lexer::new(&src);
-        matchseq!(lex, Token::Number(1.234f64));
+        let src = "1.234";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex, Token::Float(1.234f64));
     }
     #[test]
     fn lex_num_hex_int() {
-        let src = format!("0xFFFF");
-        let mut lex = Lexer::new(&src);
-        matchseq!(lex, Token::Number(65535f64));
+        let src = "0xFFFF";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex, Token::Integer(65535));
+    }
+    #[test]
+    fn lex_num_int_overflow() {
+        let src = "9223372036854775807 9223372036854775808";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex, Token::Integer(i64::MAX), Token::Float(9223372036854775808f64));
+    }
+    #[test]
+    fn lex_num_hex_wraparound() {
+        let src = "0xFFFFFFFFFFFFFFFF 0x7fffffffffffffff 0x10000000000000001";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex, Token::Integer(-1), Token::Integer(i64::MAX), Token::Integer(1));
+    }
+    #[test]
+    fn lex_num_followed_by_token() {
+        let src = "f(1,2.5)";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex,
+                  "f",
+                  Token::OpenParen,
+                  Token::Integer(1),
+                  Token::Comma,
+                  Token::Float(2.5),
+                  Token::CloseParen);
+    }
+    #[test]
+    fn lex_num_dec_exp_sign() {
+        let src = "1e+5 1e-5 1E5 .5 3. 3.e2";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex,
+                  Token::Float(1e5),
+                  Token::Float(1e-5),
+                  Token::Float(1e5),
+                  Token::Float(0.5),
+                  Token::Float(3.0),
+                  Token::Float(300.0));
+    }
+    #[test]
+    fn lex_num_hex_float() {
+        let src = "0x1.8p3 0x.1P-4 0xA.8 0x1p+4 0X1P-1074 0xA";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex,
+                  Token::Float(12.0),
+                  Token::Float(0.00390625),
+                  Token::Float(10.5),
+                  Token::Float(16.0),
+                  Token::Float(f64::from_bits(1)),
+                  Token::Integer(10));
+    }
+    #[test]
+    fn lex_num_malformed() {
+        let cases = vec![("3x", "3x"), ("1..2", "1..2"), ("1e", "1e"), ("0x.p1", "0x.p1"), ("0x1p", "0x1p")];
+        for (src, num) in cases {
+            let mut lex = Lexer::new(src);
+            matcherr!(lex, LexErrorKind::MalformedNumber(num.to_string()));
+        }
     }
     #[test]
-    #[should_panic]
     fn lex_num_hex_eof() {
-        let src = format!("0x");
-        let mut lex = Lexer::new(&src);
-        matchseq!(lex);
+        let src = "0x";
+        let mut lex = Lexer::new(src);
+        matcherr!(lex, LexErrorKind::MalformedNumber("0x".to_string()));
     }
     #[test]
-    #[should_panic]
     fn lex_num_hex_misformed() {
-        let src = format!("0xy");
-        let mut lex = Lexer::new(&src);
-        matchseq!(lex);
+        let src = "0xy";
+        let mut lex = Lexer::new(src);
+        matcherr!(lex, LexErrorKind::MalformedNumber("0xy".to_string()));
     }
     #[test]
     fn lex_str() {
-        let src = format!("\"Hello, '\\\"world!\\\"'\"\n'ayoo\\a'");
-        let mut lex = Lexer::new(&src);
+        let src = "\"Hello, '\\\"world!\\\"'\"\n'ayoo\\a'";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex,
+                  Token::StaticString("Hello, '\"world!\"'".as_bytes().into()),
+                  Token::StaticString("ayoo\x07".as_bytes().into()));
+    }
+    #[test]
+    fn lex_str_followed_by_token() {
+        let src = "f('x')";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex,
+                  "f",
+                  Token::OpenParen,
+                  Token::StaticString("x".as_bytes().into()),
+                  Token::CloseParen);
+    }
+    #[test]
+    fn lex_str_long() {
+        let src = "[[\nhello\r\n]] [==[a]]b]=]c]==] [=[]=]";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex,
+                  Token::StaticString("hello\n".as_bytes().into()),
+                  Token::StaticString("a]]b]=]c".as_bytes().into()),
+                  Token::StaticString("".as_bytes().into()));
+    }
+    #[test]
+    fn lex_str_long_position() {
+        let src = "[[\none\ntwo]] x";
+        let mut lex = Lexer::new(src);
+        lex.next();
+        match lex.next() {
+            Some(Ok(Lexeme(tk, pos, _))) => {
+                assert_eq!(tk, Token::from("x"));
+                assert_eq!((pos.line(), pos.pos()), (3, 6));
+            }
+            _ => panic!("expected an identifier"),
+        }
+    }
+    #[test]
+    fn lex_line_breaks() {
+        let lines = |src: &str| {
+            Lexer::new(src).map(|res| res.unwrap().1.line()).collect::<Vec<_>>()
+        };
+        assert_eq!(lines("a\rb\rc"), [1, 2, 3]);
+        assert_eq!(lines("a\r\nb\n\rc\n\nd\r\re"), [1, 2, 3, 5, 7]);
+        assert_eq!(lines("a\n\r\nb"), [1, 3]);
+        assert_eq!(lines("[[\r\rx\r\ny]] --[[\n\r]] z"), [1, 4, 5]);
+        assert_eq!(lines("#!lua\r-- c\rx -- d\r\ny"), [1, 2, 3, 3, 4]);
+        let mut lex = Lexer::new("-- c\rlocal x = 1\r");
+        matchseq!(lex, Token::Comment("c".into()), Keyword::Local, "x", Token::Assignment, Token::Integer(1));
+    }
+    #[test]
+    fn lex_str_long_invalid() {
+        let src = "[== x";
+        let mut lex = Lexer::new(src);
+        matcherr!(lex, LexErrorKind::InvalidLongDelimiter);
+        let src = "[[ x";
+        let mut lex = Lexer::new(src);
+        matcherr!(lex, LexErrorKind::UnterminatedString);
+    }
+    #[test]
+    fn lex_comment_long() {
+        let src = "--[[\nhello\nworld]] x --[==[ ]] ]==] --[ y\n--\nz";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex,
+                  Token::Comment("hello\nworld".into()),
+                  "x",
+                  Token::Comment(" ]] ".into()),
+                  Token::Comment("[ y".into()),
+                  Token::Comment("".into()),
+                  "z");
+    }
+    #[test]
+    fn lex_comment_long_unterminated() {
+        let src = "--[=[ ]]";
+        let mut lex = Lexer::new(src);
+        matcherr!(lex, LexErrorKind::UnterminatedComment);
+    }
+    #[test]
+    fn lex_str_escapes() {
+        let src = "'\\x41\\65\\0679\\u{48}\\u{20AC}' 'a\\z  \n  b' 'c\\\nd'";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex,
+                  Token::StaticString("AAC9H\u{20AC}".as_bytes().into()),
+                  Token::StaticString("ab".as_bytes().into()),
+                  Token::StaticString("c\nd".as_bytes().into()));
+    }
+    #[test]
+    fn lex_str_bytes() {
+        let src = b"'\\xff\\0' \"\xfe\"";
+        let mut lex = Lexer::new(&src[..]);
+        matchseq!(lex,
+                  Token::StaticString(vec![0xff, 0x00].into()),
+                  Token::StaticString(vec![0xfe].into()));
+        let src = "'\\u{7FFFFFFF}'";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex, Token::StaticString(vec![0xfd, 0xbf, 0xbf, 0xbf, 0xbf, 0xbf].into()));
+    }
+    #[test]
+    fn lex_ident_unicode() {
+        let src = "größe = 1";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex, "größe", Token::Assignment, Token::Integer(1));
+        let src = b"a\xff";
+        let mut lex = Lexer::new(&src[..]);
+        matcherr!(lex, LexErrorKind::InvalidUtf8);
+    }
+    #[test]
+    fn lex_str_escapes_invalid() {
+        let cases = vec![("'\\x4g'", LexErrorKind::InvalidHexEscape),
+                         ("'\\256'", LexErrorKind::DecimalEscapeTooLarge),
+                         ("'\\u48'", LexErrorKind::MalformedUnicodeEscape),
+                         ("'\\u{}'", LexErrorKind::MalformedUnicodeEscape),
+                         ("'\\u{48'", LexErrorKind::MalformedUnicodeEscape),
+                         ("'\\u{80000000}'", LexErrorKind::UnicodeEscapeTooLarge)];
+        for (src, kind) in cases {
+            let mut lex = Lexer::new(src);
+            matcherr!(lex, kind);
+        }
+    }
+    #[test]
+    fn lex_spans() {
+        let src = "local s = 'a\\tb' -- c\n[[d]]";
+        let tokens: Vec<_> = Lexer::new(src).map(Result::unwrap).collect();
+        let texts: Vec<_> = tokens.iter().map(|&Lexeme(_, _, span)| &src[span.start..span.end]).collect();
+        assert_eq!(texts, vec!["local", "s", "=", "'a\\tb'", "-- c", "[[d]]"]);
+    }
+    #[test]
+    fn lex_borrowed() {
+        let src = "name 'plain' 'esc\\n' [[long]] [[a\r\nb]] --[[comment]]";
+        let tokens: Vec<_> = Lexer::new(src).map(|res| res.unwrap().0).collect();
+        let borrowed: Vec<_> = tokens.iter()
+            .map(|tk| {
+                // Identifiers are interned, so they never allocate once seen.
+                matches!(*tk,
+                         Token::Ident(_) |
+                         Token::StaticString(Cow::Borrowed(_)) |
+                         Token::Comment(Cow::Borrowed(_)))
+            })
+            .collect();
+        assert_eq!(borrowed, vec![true, true, false, true, false, true]);
+        assert_eq!(tokens[4], Token::StaticString(b"a\nb"[..].into()));
+        let owned: Token<'static> = tokens[1].clone().into_owned();
+        assert_eq!(owned, Token::StaticString(b"plain"[..].into()));
+    }
+    #[test]
+    fn lex_str_invalid_escape() {
+        let src = "'\\q'";
+        let mut lex = Lexer::new(src);
+        matcherr!(lex, LexErrorKind::InvalidEscape('q'));
+    }
+    #[test]
+    fn lex_str_unterminated() {
+        let src = "'hello\nworld'";
+        let mut lex = Lexer::new(src);
+        matcherr!(lex, LexErrorKind::UnterminatedString);
+    }
+    #[test]
+    fn lex_op_bitwise() {
+        let src = "a & b | c ~ d ~= ~e << f >> g // h / i <= j >= k";
+        let mut lex = Lexer::new(src);
+        matchseq!(lex,
+                  "a",
+                  Token::BitAnd,
+                  "b",
+                  Token::BitOr,
+                  "c",
+                  Token::BitXorOrNot,
+                  "d",
+                  Token::NotEqual,
+                  Token::BitXorOrNot,
+                  "e",
+                  Token::ShiftLeft,
+                  "f",
+                  Token::ShiftRight,
+                  "g",
+                  Token::FloorDiv,
+                  "h",
+                  Token::Div,
+                  "i",
+                  Token::LessThanEqual,
+                  "j",
+                  Token::GreaterThanEqual,
+                  "k");
+    }
+    #[test]
+    fn lex_unexpected_char() {
+        let src = "a ! b";
+        let mut lex = Lexer::new(src);
+        matcherr!(lex, LexErrorKind::UnexpectedCharacter('!'));
+    }
+    #[test]
+    fn lex_dialect_lua51() {
+        let options = Options::new(Dialect::Lua51);
+        let src = "goto x 3 0x10 1e2";
+        let mut lex = Lexer::with_options(src, options);
+        matchseq!(lex, "goto", "x", Token::Float(3.0), Token::Float(16.0), Token::Float(100.0));
+        let mut lex = Lexer::with_options("a // b", options);
+        matcherr!(lex, LexErrorKind::Unsupported("`//`", Dialect::Lua51));
+        let mut lex = Lexer::with_options("::a::", options);
+        matcherr!(lex, LexErrorKind::Unsupported("`::`", Dialect::Lua51));
+        let mut lex = Lexer::with_options("0x1p4", options);
+        matcherr!(lex, LexErrorKind::Unsupported("hexadecimal float", Dialect::Lua51));
+    }
+    #[test]
+    fn lex_dialect_lua52() {
+        let options = Options::new(Dialect::Lua52);
+        let src = "goto ::a:: 0xFFFFFFFFFFFFFFFF '\\x41\\z  '";
+        let mut lex = Lexer::with_options(src, options);
+        matchseq!(lex,
+                  Keyword::Goto,
+                  Token::DoubleColon,
+                  "a",
+                  Token::DoubleColon,
+                  Token::Float(18446744073709551615.0),
+                  Token::StaticString(b"A"[..].into()));
+        let mut lex = Lexer::with_options("a ~ b", options);
+        matcherr!(lex, LexErrorKind::Unsupported("`~`", Dialect::Lua52));
+        let mut lex = Lexer::with_options("'\\u{41}'", options);
+        matcherr!(lex, LexErrorKind::Unsupported("`\\u`", Dialect::Lua52));
+    }
+    #[test]
+    fn lex_dialect_ascii_identifiers() {
+        let mut lex = Lexer::with_options("größe", Options::new(Dialect::Lua54));
+        matcherr!(lex, LexErrorKind::UnexpectedCharacter('ö'));
+    }
+    #[test]
+    fn lex_interpolation() {
+        let mut lex = Lexer::new("$'a'");
+        matcherr!(lex, LexErrorKind::Unsupported("string interpolation", Dialect::Lua54));
+        let options = Options {
+            interpolation: true,
+            ..Options::default()
+        };
+        let src = "$\"a = {a}, {{b}} {t[\"}\"] .. $'{x}'}\"";
+        let token = Lexer::with_options(src, options).next().unwrap().unwrap().0;
+        let fragments = match token {
+            Token::Interpolation(ref fragments) => fragments,
+            _ => panic!("expected an interpolated string"),
+        };
+        fn tokens<'a>(fragment: &Fragment<'a>) -> Vec<Token<'a>> {
+            match *fragment {
+                Fragment::Expr(ref lexemes) => lexemes.iter().map(|lexeme| lexeme.0.clone()).collect(),
+                Fragment::Text(_) => panic!("expected an expression"),
+            }
+        }
+        assert_eq!(fragments.len(), 4);
+        assert_eq!(fragments[0], Fragment::Text(b"a = "[..].into()));
+        assert_eq!(tokens(&fragments[1]), vec![Token::from("a")]);
+        assert_eq!(fragments[2], Fragment::Text(b", {b} "[..].into()));
+        assert_eq!(tokens(&fragments[3])[..5],
+                   [Token::from("t"),
+                    Token::OpenBracket,
+                    Token::StaticString(b"}"[..].into()),
+                    Token::CloseBracket,
+                    Token::Concat]);
+        assert_eq!(token.to_string(), "$\"a = {a}, {{b}} {t[\"}\"]..$\"{x}\"}\"");
+        let mut lex = Lexer::with_options("$\"{a\"", options);
+        matcherr!(lex, LexErrorKind::UnterminatedString);
+        let mut lex = Lexer::with_options("$a", options);
+        matcherr!(lex, LexErrorKind::UnexpectedCharacter('$'));
+    }
+    #[test]
+    fn lex_lambda() {
+        let options = Options {
+            lambda: true,
+            ..Options::new(Dialect::Lua51)
+        };
+        let mut lex = Lexer::with_options("|x| x", options);
+        matchseq!(lex, Token::BitOr, "x", Token::BitOr, "x");
+        let mut lex = Lexer::with_options("|x| x", Options::new(Dialect::Lua51));
+        matcherr!(lex, LexErrorKind::Unsupported("`|`", Dialect::Lua51));
+    }
+    #[test]
+    fn lex_compound_assignment() {
+        let options = Options {
+            compound_assignment: true,
+            ..Options::default()
+        };
+        let src = "+= -= *= /= //= %= ^= ..= &= |= <<= >>= ~= .. ... - =";
+        let mut lex = Lexer::with_options(src, options);
         matchseq!(lex,
-                  Token::StaticString(format!("Hello, '\"world!\"'")),
-                  Token::StaticString(format!("ayoo\x07")));
+                  Token::AddAssign,
+                  Token::SubAssign,
+                  Token::MulAssign,
+                  Token::DivAssign,
+                  Token::FloorDivAssign,
+                  Token::ModAssign,
+                  Token::PowerAssign,
+                  Token::ConcatAssign,
+                  Token::BitAndAssign,
+                  Token::BitOrAssign,
+                  Token::ShiftLeftAssign,
+                  Token::ShiftRightAssign,
+                  Token::NotEqual,
+                  Token::Concat,
+                  Token::VarArgs,
+                  Token::SubOrMinus,
+                  Token::Assignment);
+        let mut lex = Lexer::new("a+=1");
+        matchseq!(lex, "a", Token::Add, Token::Assignment, Token::Integer(1));
+    }
+    macro_rules! matchrelex {
+        ($src:expr, $start:expr, $end:expr, $replacement:expr, $relexed:expr) => {
+            matchrelex!($src, $start, $end, $replacement, $relexed, Options::default())
+        };
+        ($src:expr, $start:expr, $end:expr, $replacement:expr, $relexed:expr, $options:expr) => {{
+            let src: &[u8] = $src.as_bytes();
+            let options = $options;
+            let mut tokens: Vec<_> = Lexer::with_options(src, options).map(|res| res.unwrap().into_owned()).collect();
+            let edit = TextEdit {
+                span: Span { start: $start, end: $end },
+                replacement: $replacement.as_bytes(),
+            };
+            let new_src = edit.apply(src);
+            let range = relex(&mut tokens, &new_src, &edit, options).unwrap();
+            let expected: Vec<_> = Lexer::with_options(&new_src, options).map(|res| res.unwrap()).collect();
+            assert_eq!(tokens, expected);
+            assert_eq!(range.len(), $relexed);
+        }};
+    }
+    #[test]
+    fn lex_relex() {
+        let src = "local x = 1\nlocal y = x + 2 -- sum\nprint(x, y)\n";
+        // Rename a variable, lengthening the line.
+        matchrelex!(src, 6, 7, "value", 2);
+        // Join two lines.
+        matchrelex!(src, 11, 12, " ", 2);
+        // Extend an identifier at its end.
+        matchrelex!(src, 7, 7, "z", 2);
+        // Edit the first and the last token.
+        matchrelex!(src, 0, 5, "global", 1);
+        matchrelex!(src, 45, 46, "]", 3);
+        // Comment out the last line.
+        matchrelex!(src, 35, 35, "--", 2);
+        // Move the tokens inside an interpolated string.
+        let options = Options {
+            interpolation: true,
+            ..Options::default()
+        };
+        matchrelex!("x = 1\ny = $\"a{b}\"", 4, 5, "12345", 2, options);
+        matchrelex!("x = 1 y = $\"a{b .. $\"{c}\"}\"", 0, 1, "xyz", 1, options);
+    }
+    #[test]
+    fn lex_relex_long_brackets() {
+        let src = "a = 1 b = 2 --[[ c = 3 ]] d = 4";
+        // Open a long comment, which then extends over the existing one.
+        matchrelex!(src, 6, 6, "--[[", 2);
+        // Close the long comment early.
+        matchrelex!(src, 17, 17, "]]", 7);
+        // Turn the long comment into a line comment.
+        matchrelex!(src, 14, 16, "", 2);
+        // Edit inside the long comment.
+        matchrelex!(src, 17, 18, "e", 2);
+    }
+    #[test]
+    fn lex_relex_error() {
+        let src = "a = 'b'";
+        let mut tokens: Vec<_> = Lexer::new(src).map(|res| res.unwrap().into_owned()).collect();
+        let expected = tokens.clone();
+        let edit = TextEdit {
+            span: Span { start: 6, end: 7 },
+            replacement: b"",
+        };
+        let res = relex(&mut tokens, &edit.apply(src.as_bytes()), &edit, Default::default());
+        assert_eq!(res.map_err(|err| err.kind), Err(LexErrorKind::UnterminatedString));
+        assert_eq!(tokens, expected);
+    }
+    /// A reader that returns a few bytes at a time and then fails, if desired.
+    struct Trickle<'a>(&'a [u8], usize, bool);
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() && self.2 {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
+            }
+            let len = self.1.min(buf.len()).min(self.0.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+    #[test]
+    fn lex_stream() {
+        let srcs = ["#!/bin/lua\nlocal s = [==[\nlong ]] string]==] .. 'a\\x41\\z\n b' -- c\nreturn 0x1p4 + 1e-2 // 3...~= x",
+                    "--[[ unterminated",
+                    "a = 'b\\x4",
+                    "t[ [=[ x ]=] ] = 1 ~",
+                    "x = [== y"];
+        for src in srcs.iter() {
+            let expected: Vec<_> = Lexer::new(src).map(|res| res.map(Lexeme::into_owned)).collect();
+            for &size in &[1, 2, 3, 7, 4096] {
+                let lex = StreamLexer::new(Trickle(src.as_bytes(), size, false));
+                assert_eq!(lex.collect::<Vec<_>>(), expected);
+            }
+        }
+        let options = Options {
+            interpolation: true,
+            ..Options::default()
+        };
+        for src in ["x = $\"v = {abcdef + 1}\" y", "s = $\"{ {a = 'b'} }\" .. $\"{'unterminated", "s = $\"{x"].iter() {
+            let expected: Vec<_> = Lexer::with_options(src, options).map(|res| res.map(Lexeme::into_owned)).collect();
+            for &size in &[1, 2, 3, 7, 9, 4096] {
+                let lex = StreamLexer::with_options(Trickle(src.as_bytes(), size, false), options);
+                assert_eq!(lex.collect::<Vec<_>>(), expected, "{:?} in chunks of {}", src, size);
+            }
+        }
+    }
+    #[test]
+    fn lex_stream_io_error() {
+        let mut lex = StreamLexer::new(Trickle(b"local x", 3, true));
+        assert_eq!(lex.next().map(|res| res.unwrap().0), Some(Token::Keyword(Keyword::Local)));
+        match lex.next() {
+            Some(Err(err)) => assert_eq!(err.kind, LexErrorKind::Io(io::ErrorKind::BrokenPipe)),
+            _ => panic!("expected an error"),
+        }
+        assert!(lex.next().is_none());
+    }
+    #[test]
+    fn lex_ident_interned() {
+        let src = "name other name";
+        let tokens: Vec<_> = Lexer::new(src).map(|res| res.unwrap().0).collect();
+        assert_eq!(tokens[0], tokens[2]);
+        assert_ne!(tokens[0], tokens[1]);
+        assert_eq!(tokens[0], Token::Ident(Symbol::intern(b"name")));
+        assert_eq!(Symbol::intern("name").as_bytes(), b"name");
+        assert_eq!(Symbol::intern(&b"\xff"[..]).to_text(), "\u{fffd}");
+        // Unused strings are freed, but symbols in use stay unique.
+        let kept = Symbol::intern("kept");
+        for idx in 0..10000 {
+            Symbol::intern(&format!("unused{}", idx));
+        }
+        assert_eq!(Symbol::intern("kept"), kept);
+        assert_eq!(Symbol::intern("kept").as_bytes(), b"kept");
+    }
+    #[test]
+    fn token_display() {
+        let tokens = [Token::Keyword(Keyword::Function),
+                      Token::Integer(42),
+                      Token::Integer(-1),
+                      Token::Float(1.0),
+                      Token::Float(1e100),
+                      Token::Float(0.1),
+                      Token::Float(1.0 / 0.0),
+                      Token::StaticString(b"a\"b\\\n\x00\x01"[..].into()),
+                      Token::StaticString(vec![0xC3, 0xA4, 0xFF].into()),
+                      Token::Comment("line".into()),
+                      Token::Comment("a ]] b\n".into()),
+                      Token::Comment(" x".into()),
+                      Token::FloorDiv,
+                      Token::NotEqual];
+        let texts: Vec<_> = tokens.iter().map(Token::to_string).collect();
+        assert_eq!(texts,
+                   ["function",
+                    "42",
+                    "0xFFFFFFFFFFFFFFFF",
+                    "1.0",
+                    "1e100",
+                    "0.1",
+                    "1e9999",
+                    "\"a\\\"b\\\\\\n\\000\\001\"",
+                    "\"\u{e4}\\255\"",
+                    "-- line",
+                    "--[=[a ]] b\n]=]",
+                    "--[[ x]]",
+                    "//",
+                    "~="]);
+        for (token, text) in tokens.iter().zip(texts.iter()) {
+            let mut lex = Lexer::new(text);
+            assert_eq!(lex.next().map(|res| res.unwrap().0).as_ref(), Some(token));
+        }
+    }
+    #[test]
+    fn token_to_source() {
+        let src = "#!/bin/lua\nlocal t = {a.b, - -1, 1 .. 2, a..b, x[ [=[s]=] ], ...} -- c\nreturn t.a//2 ~= ~t <= 0x7FFFFFFFFFFFFFFF";
+        let tokens: Vec<_> = Lexer::new(src).map(|res| res.unwrap().0).collect();
+        let regenerated = to_source(&tokens);
+        assert_eq!(regenerated,
+                   "#!/bin/lua\nlocal t={a.b,- -1,1 ..2,a..b,x[\"s\"],...}-- c\nreturn t.a//2~=~t<=9223372036854775807");
+        let relexed: Vec<_> = Lexer::new(&regenerated).map(|res| res.unwrap().0).collect();
+        assert_eq!(relexed, tokens);
+    }
+    #[test]
+    fn lex_lossless_roundtrip() {
+        let src = "#!/usr/bin/env lua\r\n--[==[ header\n]==]\nlocal x = 1 -- one\n\n\tx = x .. 'a\\z\n  b' --[[ c ]] -- d\r\nreturn x \x0c ";
+        let mut buf = Vec::new();
+        for res in LosslessLexer::new(src) {
+            match res {
+                Ok(lexeme) => lexeme.write_to(&mut buf),
+                Err(err) => panic!("{}", err),
+            }
+        }
+        assert_eq!(buf, src.as_bytes());
+    }
+    #[test]
+    fn lex_lossless_trivia() {
+        let src = "-- a\nx -- b\n  y\n";
+        let lexemes: Vec<_> = LosslessLexer::new(src).map(|res| res.unwrap()).collect();
+        assert_eq!(lexemes.len(), 3);
+        let kinds = |trivia: &[Trivia]| trivia.iter().map(|trivia| trivia.kind).collect::<Vec<_>>();
+        assert_eq!(lexemes[0].text, b"x");
+        assert_eq!(kinds(&lexemes[0].leading), [TriviaKind::Comment, TriviaKind::Newline]);
+        assert_eq!(kinds(&lexemes[0].trailing), [TriviaKind::Whitespace, TriviaKind::Comment, TriviaKind::Newline]);
+        assert_eq!(lexemes[0].trailing[1].text, b"-- b");
+        assert_eq!(lexemes[1].text, b"y");
+        assert_eq!(kinds(&lexemes[1].leading), [TriviaKind::Whitespace]);
+        assert_eq!(kinds(&lexemes[1].trailing), [TriviaKind::Newline]);
+        assert!(lexemes[2].lexeme.is_none());
+        assert!(lexemes[2].leading.is_empty());
+    }
+    fn parse_with(src: &str, options: Options) -> Result<Block, ParseError> {
+        let tokens = Lexer::with_options(src, options).collect::<Result<Vec<_>, _>>().unwrap();
+        Parser::with_options(&tokens, options).parse()
+    }
+    fn parse(src: &str) -> Result<Block, ParseError> {
+        parse_with(src, Options::default())
+    }
+    fn parse_expr(src: &str, options: Options) -> Expr {
+        match parse_with(&format!("return {}", src), options).unwrap().0.pop().map(|stmt| stmt.kind) {
+            Some(StmtKind::Return(mut exprs)) => exprs.pop().unwrap(),
+            _ => unreachable!(),
+        }
+    }
+    fn name(val: &str) -> Name {
+        Name {
+            symbol: Symbol::from(val),
+            span: Span::default(),
+        }
+    }
+    fn stmt(kind: StmtKind) -> Stmt {
+        Stmt::new(kind, Span::default())
+    }
+    fn expr(kind: ExprKind) -> Expr {
+        Expr::new(kind, Span::default())
+    }
+    fn var(val: &str) -> Expr {
+        expr(ExprKind::Name(name(val)))
+    }
+    fn int(val: i64) -> Expr {
+        expr(ExprKind::Integer(val))
+    }
+    fn string(val: &str) -> Expr {
+        expr(ExprKind::StaticString(val.as_bytes().to_vec()))
+    }
+    fn call(func: Expr, args: Vec<Expr>) -> Expr {
+        expr(ExprKind::Call(Box::new(func), args))
+    }
+    fn concat(lhs: Expr, rhs: Expr) -> Expr {
+        expr(ExprKind::Concat(Box::new(lhs), Box::new(rhs)))
+    }
+    fn arith(op: ArithmeticOp, lhs: Expr, rhs: Expr) -> Expr {
+        expr(ExprKind::Operation(LuaOperation::Arithmetic(op), Box::new(lhs), Box::new(rhs)))
+    }
+    fn body(params: Vec<Name>, varargs: bool, block: Vec<Stmt>) -> FunctionBody {
+        FunctionBody {
+            params,
+            varargs,
+            block: Block(block),
+        }
+    }
+    #[test]
+    fn parse_stmts() {
+        let block = parse("#!/usr/bin/lua\n; do end -- comment\n a, b = 1, nil; f() ::top:: goto top \
+                           while true do break end repeat until false").unwrap();
+        assert_eq!(block.0, vec![
+            stmt(StmtKind::Do(Block(vec![]))),
+            stmt(StmtKind::Set(vec![LValue::Name(name("a")), LValue::Name(name("b"))],
+                               vec![int(1), expr(ExprKind::Nil)])),
+            stmt(StmtKind::Expr(call(var("f"), vec![]))),
+            stmt(StmtKind::Label(name("top"))),
+            stmt(StmtKind::Goto(name("top"))),
+            stmt(StmtKind::While(expr(ExprKind::True), Block(vec![stmt(StmtKind::Break)]))),
+            stmt(StmtKind::Repeat(expr(ExprKind::False), Block(vec![]))),
+        ]);
+    }
+    #[test]
+    fn parse_control_flow() {
+        let block = parse("if a then elseif b then return else return 1, 2; end \
+                           for i = 1, 10 do end for k, v in next, t do end").unwrap();
+        assert_eq!(block.0, vec![
+            stmt(StmtKind::If(vec![(var("a"), Block(vec![])),
+                                   (var("b"), Block(vec![stmt(StmtKind::Return(vec![]))]))],
+                              Some(Block(vec![stmt(StmtKind::Return(vec![int(1), int(2)]))])))),
+            stmt(StmtKind::ForNum(name("i"), int(1), int(10), None, Block(vec![]))),
+            stmt(StmtKind::ForIn(vec![name("k"), name("v")], vec![var("next"), var("t")], Block(vec![]))),
+        ]);
+    }
+    #[test]
+    fn parse_functions() {
+        let block = parse("function a.b:c(x, ...) return ... end local function f() end \
+                           local x <const>, y <close> = (g)('s')").unwrap();
+        assert_eq!(block.0, vec![
+            stmt(StmtKind::Function(FuncName { path: vec![name("a"), name("b")], method: Some(name("c")) },
+                                    body(vec![name("x")],
+                                         true,
+                                         vec![stmt(StmtKind::Return(vec![expr(ExprKind::Dots)]))]))),
+            stmt(StmtKind::LocalFunction(name("f"), body(vec![], false, vec![]))),
+            stmt(StmtKind::Local(vec![(name("x"), Some(Attrib::Const)), (name("y"), Some(Attrib::Close))],
+                                 vec![call(expr(ExprKind::Paren(Box::new(var("g")))), vec![string("s")])])),
+        ]);
+    }
+    #[test]
+    fn parse_errors() {
+        let err = parse("while x do\n f()").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::Expected("`end`", None));
+        assert_eq!(err.pos.line(), 1);
+        assert_eq!(err.to_string(), "[1, 0] `end` expected near <eof>");
+        let err = parse("x").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::Unexpected(None));
+        let err = parse("return 1 x = 2").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::Expected("<eof>", Some(Token::Ident(Symbol::from("x")))));
+        let err = parse("local x <static> = 1").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnknownAttribute(Symbol::from("static")));
+        let err = parse("local x <close>, y <close> = 1").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::MultipleToBeClosed);
+        let err = parse("function f() return ... end").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::VarArgsOutsideVarArgFunction);
+        let err = parse_with("local x <const> = 1", Options::new(Dialect::Lua53)).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::Unsupported("attributes", Dialect::Lua53));
+    }
+    #[test]
+    fn parse_operators() {
+        let options = Options::default();
+        assert_eq!(parse_expr("1 + 2 * 3 - 4", options),
+                   arith(ArithmeticOp::Sub,
+                         arith(ArithmeticOp::Add, int(1), arith(ArithmeticOp::Mul, int(2), int(3))),
+                         int(4)));
+        assert_eq!(parse_expr("-a ^ b ^ c", options),
+                   expr(ExprKind::Unary(ArithmeticOp::Unm,
+                                        Box::new(arith(ArithmeticOp::Pow,
+                                                       var("a"),
+                                                       arith(ArithmeticOp::Pow, var("b"), var("c")))))));
+        assert_eq!(parse_expr("a .. b .. c", options), concat(var("a"), concat(var("b"), var("c"))));
+        let equal = expr(ExprKind::Operation(LuaOperation::Logical(LogicalOp::Equal),
+                                             Box::new(expr(ExprKind::Not(Box::new(var("a"))))),
+                                             Box::new(var("b"))));
+        let and = expr(ExprKind::And(Box::new(var("c")), Box::new(expr(ExprKind::Len(Box::new(var("d")))))));
+        assert_eq!(parse_expr("not a == b or c and #d", options),
+                   expr(ExprKind::Or(Box::new(equal), Box::new(and))));
+        assert_eq!(parse_expr("a | b ~ c & d << e", options),
+                   arith(ArithmeticOp::BitwiseOp(BitwiseOp::Or),
+                         var("a"),
+                         arith(ArithmeticOp::BitwiseOp(BitwiseOp::Xor),
+                               var("b"),
+                               arith(ArithmeticOp::BitwiseOp(BitwiseOp::And),
+                                     var("c"),
+                                     arith(ArithmeticOp::Shl, var("d"), var("e"))))));
+        let not = expr(ExprKind::Unary(ArithmeticOp::BitwiseOp(BitwiseOp::Not), Box::new(var("a"))));
+        assert_eq!(parse_expr("~a // 2 >= 1 .. 2", options),
+                   expr(ExprKind::Operation(LuaOperation::Logical(LogicalOp::GreaterThanOrEqual),
+                                            Box::new(arith(ArithmeticOp::IDiv, not, int(2))),
+                                            Box::new(concat(int(1), int(2))))));
+        assert_eq!(parse_expr("(a + b) * c", options),
+                   arith(ArithmeticOp::Mul,
+                         expr(ExprKind::Paren(Box::new(arith(ArithmeticOp::Add, var("a"), var("b"))))),
+                         var("c")));
+        let err = parse("return 1 +").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::Unexpected(None));
+    }
+    #[test]
+    fn parse_interpolation() {
+        let options = Options {
+            interpolation: true,
+            ..Options::default()
+        };
+        let tostring = |arg| call(var("tostring"), vec![arg]);
+        let paren = |inner| expr(ExprKind::Paren(Box::new(inner)));
+        assert_eq!(parse_expr("$\"a = {a + 1}!\"", options),
+                   paren(concat(string("a = "),
+                                concat(tostring(arith(ArithmeticOp::Add, var("a"), int(1))), string("!")))));
+        assert_eq!(parse_expr("$\"{x}{y}\" .. z", options),
+                   concat(paren(concat(tostring(var("x")), tostring(var("y")))), var("z")));
+        assert_eq!(parse_expr("$\"\"", options), string(""));
+        assert_eq!(parse_expr("$\"{x}\"", options), tostring(var("x")));
+        let err = parse_with("return $\"{a b}\"", options).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::Expected("`}`", Some(Token::from("b"))));
+    }
+    #[test]
+    fn parse_prefix_exprs() {
+        let block = parse("a.b[c]:m(...) t.x, t[1] = f 's', f {} print(function(x, ...) end)").unwrap();
+        let field = expr(ExprKind::Field(Box::new(var("a")), name("b")));
+        let index = expr(ExprKind::Index(Box::new(field), Box::new(var("c"))));
+        let table = expr(ExprKind::Table(TableConstructor(vec![])));
+        assert_eq!(block.0, vec![
+            stmt(StmtKind::Expr(expr(ExprKind::MethodCall(Box::new(index), name("m"), vec![expr(ExprKind::Dots)])))),
+            stmt(StmtKind::Set(vec![LValue::Field(var("t"), name("x")), LValue::Index(var("t"), int(1))],
+                               vec![call(var("f"), vec![string("s")]), call(var("f"), vec![table])])),
+            stmt(StmtKind::Expr(call(var("print"),
+                                     vec![expr(ExprKind::Function(body(vec![name("x")], true, vec![])))]))),
+        ]);
+        let err = parse("f() = 1").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::Unexpected(Some(Token::Assignment)));
+        let err = parse("a.b").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::Unexpected(None));
+    }
+    #[test]
+    fn parse_table() {
+        let block = parse("return {1, a = 2; [3] = 4, {},}").unwrap();
+        assert_eq!(block.0, vec![
+            stmt(StmtKind::Return(vec![expr(ExprKind::Table(TableConstructor(vec![
+                TableField::Positional(int(1)),
+                TableField::Named(name("a"), int(2)),
+                TableField::Bracketed(int(3), int(4)),
+                TableField::Positional(expr(ExprKind::Table(TableConstructor(vec![])))),
+            ])))])),
+        ]);
+        let err = parse("return {1 2}").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::Expected("`}`", Some(Token::Integer(2))));
+    }
+    #[test]
+    fn parse_lambda() {
+        let options = Options {
+            lambda: true,
+            ..Options::default()
+        };
+        let lambda = |params, varargs, value| {
+            expr(ExprKind::Function(body(params, varargs, vec![stmt(StmtKind::Return(vec![value]))])))
+        };
+        assert_eq!(parse_expr("|x, y| x | y", options),
+                   lambda(vec![name("x"), name("y")],
+                          false,
+                          arith(ArithmeticOp::BitwiseOp(BitwiseOp::Or), var("x"), var("y"))));
+        assert_eq!(parse_expr("|...| ...", options), lambda(vec![], true, expr(ExprKind::Dots)));
+        assert_eq!(parse_expr("|| 1", options), lambda(vec![], false, int(1)));
+        let err = parse_with("return |x| ...", options).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::VarArgsOutsideVarArgFunction);
+        let err = parse("return |x| x").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::Unexpected(Some(Token::BitOr)));
+        // Before Lua 5.3, `|` only delimits lambdas.
+        let options = Options {
+            lambda: true,
+            compound_assignment: true,
+            ..Options::new(Dialect::Lua51)
+        };
+        assert_eq!(parse_expr("|x| x", options), lambda(vec![name("x")], false, var("x")));
+        for src in &["x = 1 | 2", "f = |x| x | 1", "x |= 2"] {
+            let err = parse_with(src, options).unwrap_err();
+            assert_eq!(err.kind, ParseErrorKind::Unsupported("`|`", Dialect::Lua51));
+        }
+    }
+    #[test]
+    fn parse_compound_assignment() {
+        let options = Options {
+            compound_assignment: true,
+            ..Options::default()
+        };
+        let src = "t[k] += 1 s ..= 'x' a.b.c *= t";
+        let block = parse_with(src, options).unwrap();
+        // The current value of the target is always read before the value is evaluated.
+        assert_eq!(block,
+                   parse("do local t, k = t, k local v = t[k] t[k] = v + 1 end \
+                          do local v = s s = v .. 'x' end \
+                          do local t_ = a.b local v = t_.c t_.c = v * t end").unwrap());
+        // The locals never shadow the names in the compound assignment.
+        assert_eq!(parse_with("v += f(v, v_)", options).unwrap(),
+                   parse("do local v__ = v v = v__ + f(v, v_) end").unwrap());
+        // The lowered statements span the compound assignment.
+        match block.0[0].kind {
+            StmtKind::Do(ref body) => assert!(body.0.iter().all(|stmt| stmt.span == block.0[0].span)),
+            ref kind => panic!("expected a block, found {:?}", kind),
+        }
+        assert_eq!(block.0[2].span.text(src.as_bytes()), b"a.b.c *= t");
+        let err = parse_with("f() += 1", options).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::Unexpected(Some(Token::AddAssign)));
+    }
+    #[test]
+    fn parse_recovery() {
+        let src = "local = 1\nwhile true do x end\nprint('ok')\nend\nfunction f() g( end";
+        let tokens = Lexer::new(src).collect::<Result<Vec<_>, _>>().unwrap();
+        let (block, errors) = Parser::new(&tokens).parse_recovering();
+        assert_eq!(block.0, vec![
+            stmt(StmtKind::Error),
+            stmt(StmtKind::While(expr(ExprKind::True), Block(vec![stmt(StmtKind::Error)]))),
+            stmt(StmtKind::Expr(call(var("print"), vec![string("ok")]))),
+            stmt(StmtKind::Error),
+            stmt(StmtKind::Function(FuncName { path: vec![name("f")], method: None },
+                                    body(vec![], false, vec![stmt(StmtKind::Error)]))),
+        ]);
+        assert_eq!(block.0[0].span.text(src.as_bytes()), b"local = 1");
+        let errors = errors.iter().map(|err| (err.pos.line(), err.kind.clone())).collect::<Vec<_>>();
+        assert_eq!(errors, vec![
+            (1, ParseErrorKind::Expected("name", Some(Token::Assignment))),
+            (2, ParseErrorKind::Unexpected(Some(Token::Keyword(Keyword::End)))),
+            (4, ParseErrorKind::Expected("<eof>", Some(Token::Keyword(Keyword::End)))),
+            (5, ParseErrorKind::Unexpected(Some(Token::Keyword(Keyword::End)))),
+        ]);
+        let err = Parser::new(&tokens).parse().unwrap_err();
+        assert_eq!(err.kind, errors[0].1);
+        // Assignments and calls on a new line start a new statement.
+        let src = "x = = 1\ny = 2\nz = (\nw = 3\n(f)()";
+        let tokens = Lexer::new(src).collect::<Result<Vec<_>, _>>().unwrap();
+        let (block, errors) = Parser::new(&tokens).parse_recovering();
+        assert_eq!(block.0, vec![
+            stmt(StmtKind::Error),
+            stmt(StmtKind::Set(vec![LValue::Name(name("y"))], vec![int(2)])),
+            stmt(StmtKind::Error),
+            stmt(StmtKind::Expr(call(expr(ExprKind::Paren(Box::new(var("f")))), vec![]))),
+        ]);
+        let errors = errors.iter().map(|err| (err.pos.line(), err.kind.clone())).collect::<Vec<_>>();
+        assert_eq!(errors, vec![
+            (1, ParseErrorKind::Unexpected(Some(Token::Assignment))),
+            (3, ParseErrorKind::Expected("`)`", Some(Token::Assignment))),
+        ]);
+        // A statement after a return statement is skipped as a whole.
+        let tokens = Lexer::new("return 1; x = 2").collect::<Result<Vec<_>, _>>().unwrap();
+        let (block, errors) = Parser::new(&tokens).parse_recovering();
+        assert_eq!(block.0.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ParseErrorKind::Expected("<eof>", Some(Token::Ident(Symbol::from("x")))));
+    }
+    #[test]
+    fn parse_spans() {
+        let src = "local t = {f(a.b, 1 + 2)}\nreturn t";
+        let tokens = Lexer::new(src).collect::<Result<Vec<_>, _>>().unwrap();
+        let block = Parser::new(&tokens).parse().unwrap();
+        let text = |span: Span| String::from_utf8(span.text(src.as_bytes()).to_vec()).unwrap();
+        assert_eq!(text(block.0[0].span), "local t = {f(a.b, 1 + 2)}");
+        assert_eq!(text(block.0[1].span), "return t");
+        let at = |needle: &str| block.node_at(src.find(needle).unwrap()).map(|node| text(node.span()));
+        assert_eq!(at("t ="), Some("t".to_string()));
+        assert_eq!(at("{"), Some("{f(a.b, 1 + 2)}".to_string()));
+        assert_eq!(at("f("), Some("f".to_string()));
+        assert_eq!(at("(a"), Some("f(a.b, 1 + 2)".to_string()));
+        assert_eq!(at(".b"), Some("a.b".to_string()));
+        assert_eq!(at("b,"), Some("b".to_string()));
+        assert_eq!(at(" + "), Some("1 + 2".to_string()));
+        assert_eq!(at("2)"), Some("2".to_string()));
+        assert_eq!(at("\n"), None);
+        match block.node_at(src.rfind('t').unwrap()) {
+            Some(Node::Name(name)) => assert_eq!(name.symbol, Symbol::from("t")),
+            node => panic!("expected a name, found {:?}", node),
+        }
+    }
+    #[test]
+    fn visit_ast() {
+        struct Names(Vec<String>);
+        impl<'ast> Visitor<'ast> for Names {
+            fn visit_name(&mut self, name: &'ast Name) {
+                self.0.push(name.symbol.to_text());
+            }
+            // Function bodies are skipped.
+            fn visit_function_body(&mut self, _: &'ast FunctionBody) {}
+        }
+        let block = parse("local a <const> = {b = c[d]} function e.f(g) h() end for i = j, k do l:m(#n) end").unwrap();
+        let mut names = Names(Vec::new());
+        names.visit_block(&block);
+        assert_eq!(names.0, ["a", "b", "c", "d", "e", "f", "i", "j", "k", "l", "m", "n"]);
+    }
+    #[test]
+    fn visit_ast_mut() {
+        struct Fold;
+        impl VisitorMut for Fold {
+            fn visit_expr_mut(&mut self, expr: &mut Expr) {
+                visit::walk_expr_mut(self, expr);
+                let sum = match expr.kind {
+                    ExprKind::Operation(LuaOperation::Arithmetic(ArithmeticOp::Add), ref lhs, ref rhs) => {
+                        match (&lhs.kind, &rhs.kind) {
+                            (&ExprKind::Integer(lhs), &ExprKind::Integer(rhs)) => Some(lhs.wrapping_add(rhs)),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+                if let Some(sum) = sum {
+                    expr.kind = ExprKind::Integer(sum);
+                }
+            }
+            fn visit_name_mut(&mut self, name: &mut Name) {
+                name.symbol = Symbol::from(&*name.symbol.to_text().to_uppercase());
+            }
+        }
+        let mut block = parse("x = f(1 + 2 + 3, {y = 4 + z})").unwrap();
+        Fold.visit_block_mut(&mut block);
+        assert_eq!(block, parse("X = F(6, {Y = 4 + Z})").unwrap());
+    }
+    #[test]
+    fn format_source() {
+        let src = "#!/usr/bin/lua\n-- config\nlocal   a<const> , b = 1,'two' -- trailing\n\n\n\
+                   function m.n:o(p) if p then return -p^2 elseif not p then return - -p end end\n\
+                   while (a) do a = a - 1 end ;(f or g)() -- call\n\
+                   t = {1, 2; x = 3, [\"y\"] = {}}\n-- final";
+        assert_eq!(format(src, FormatOptions::default()).unwrap(),
+                   "#!/usr/bin/lua\n\
+                    -- config\n\
+                    local a <const>, b = 1, \"two\" -- trailing\n\
+                    \n\
+                    function m.n:o(p)\n    \
+                        if p then\n        \
+                            return -p ^ 2\n    \
+                        elseif not p then\n        \
+                            return - -p\n    \
+                        end\n\
+                    end\n\
+                    while (a) do\n    \
+                        a = a - 1\n\
+                    end\n\
+                    ;(f or g)() -- call\n\
+                    t = {1, 2, x = 3, [\"y\"] = {}}\n\
+                    -- final\n");
+        assert_eq!(format("", FormatOptions::default()).unwrap(), "");
+        // Comments between table fields and arguments stay with them, one item per line.
+        assert_eq!(format("x = { --[[a]] 1, -- b\n 2 } -- c\ny()", FormatOptions::default()).unwrap(),
+                   "x = {\n    -- a\n    1, -- b\n    2\n} -- c\ny()\n");
+        let src = "t = {\n a = 1, -- first\n\n b = 2, -- second\n -- rest\n}";
+        assert_eq!(format(src, FormatOptions::default()).unwrap(),
+                   "t = {\n    a = 1, -- first\n\n    b = 2 -- second\n    -- rest\n}\n");
+        assert_eq!(format("f(a, -- x\n  {b} -- y\n)", FormatOptions::default()).unwrap(),
+                   "f(\n    a, -- x\n    {b} -- y\n)\n");
+        assert_eq!(format("f({ -- x\n})", FormatOptions::default()).unwrap(), "f(\n    {\n        -- x\n    }\n)\n");
+        // Other comments within a statement move after it.
+        assert_eq!(format("x = 1 + --[[a]] 2 -- b\ny()", FormatOptions::default()).unwrap(),
+                   "x = 1 + 2\n-- a\n-- b\ny()\n");
+        // Extensions are printed in the form they are shorthand for.
+        let options = FormatOptions {
+            lua: Options {
+                lambda: true,
+                interpolation: true,
+                compound_assignment: true,
+                ..Options::default()
+            },
+            ..FormatOptions::default()
+        };
+        assert_eq!(format("x += 1 t.y -= 2 f = |x| x * 2 s = $\"a{b}\" .. c", options).unwrap(),
+                   "do\n    local v = x\n    x = v + 1\nend\n\
+                    do\n    local t = t\n    local v = t.y\n    t.y = v - 2\nend\n\
+                    f = function(x)\n    return x * 2\nend\n\
+                    s = (\"a\" .. tostring(b)) .. c\n");
+        // Comments after `else` stay in the else branch.
+        assert_eq!(format("if a then x() elseif b then -- c3\nelse -- c4\nend", FormatOptions::default()).unwrap(),
+                   "if a then\n    x()\nelseif b then\n    -- c3\nelse\n    -- c4\nend\n");
+        assert_eq!(format("if a then x() -- c1\n-- c2\nelse -- c3\ny() end", FormatOptions::default()).unwrap(),
+                   "if a then\n    x() -- c1\n    -- c2\nelse\n    -- c3\n    y()\nend\n");
+        match format("x = ", FormatOptions::default()) {
+            Err(FormatError::Parse(err)) => assert_eq!(err.kind, ParseErrorKind::Unexpected(None)),
+            res => panic!("expected a parse error, found {:?}", res),
+        }
+        match format("x = \"", FormatOptions::default()) {
+            Err(FormatError::Lex(err)) => assert_eq!(err.kind, LexErrorKind::UnterminatedString),
+            res => panic!("expected a lexical error, found {:?}", res),
+        }
+    }
+    #[test]
+    fn format_options() {
+        let src = "if x then print('a', \"b'c\", {1, 2}) end";
+        let options = FormatOptions {
+            indent: Indent::Tabs,
+            quote_style: QuoteStyle::Single,
+            ..FormatOptions::default()
+        };
+        assert_eq!(format(src, options).unwrap(),
+                   "if x then\n\tprint('a', \"b'c\", {1, 2})\nend\n");
+        let options = FormatOptions {
+            indent: Indent::Spaces(2),
+            line_width: 24,
+            trailing_separator: true,
+            ..FormatOptions::default()
+        };
+        assert_eq!(format(src, options).unwrap(),
+                   "if x then\n  print(\n    \"a\",\n    \"b'c\",\n    {1, 2}\n  )\nend\n");
+        assert_eq!(format("t = {first = 1, second = 2}", options).unwrap(),
+                   "t = {\n  first = 1,\n  second = 2,\n}\n");
+    }
+    #[test]
+    fn format_round_trip() {
+        let srcs = [
+            "function Memoize(fn) fn = fn or function(x) return nil end return \
+             setmetatable({}, { __index = function(t, k) local val = fn(k) t[k] \
+             = val return val end, __call  = function(t, k) return t[k] end }) end",
+            "local x <close> = a or b and not c == d | e ~ f & g << h .. i .. j + k * -l ^ m ^ n",
+            "return ((a + b) * c) ^ (d .. e), 2 ^ -3, -(2 ^ 3), #t[1], (f)(), (...), a.b:c'd'{e}",
+            "repeat local x = f() until x >= 0x7FFFFFFFFFFFFFFF and y ~= 1.5e300",
+            "for i = 10, 1, -1 do for _, v in ipairs(t) do goto continue end ::continue:: end",
+            "x, y.z, w[1] = ... do return end local function f(a, b, ...) return a, b, ... end",
+            "-- lead\nlocal t = {\n  -- inner\n  1, -- one\n  [\"two\"] = 2,\n}\n\n\n-- tail\n",
+            "f(a, -- x\n  {b = 1, -- y\n   [c] = function() return 2 end}, g(h -- z\n)) -- w\n",
+        ];
+        for src in &srcs {
+            let formatted = format(src, FormatOptions::default()).unwrap();
+            assert_eq!(parse(&formatted).unwrap(), parse(src).unwrap(), "{}", formatted);
+            assert_eq!(format(&formatted, FormatOptions::default()).unwrap(), formatted);
+            let narrow = FormatOptions {
+                line_width: 20,
+                ..FormatOptions::default()
+            };
+            let formatted = format(src, narrow).unwrap();
+            assert_eq!(parse(&formatted).unwrap(), parse(src).unwrap(), "{}", formatted);
+            assert_eq!(format(&formatted, narrow).unwrap(), formatted);
+        }
+        // Interpolations are single operands wherever they appear.
+        let options = FormatOptions {
+            lua: Options {
+                interpolation: true,
+                ..Options::default()
+            },
+            ..FormatOptions::default()
+        };
+        for src in &["s = $\"a{b}\" .. c", "n = #$\"x{y}\" + $\"{a}\" ^ 2", "f($\"{a}{b}\", -$\"{c}\")"] {
+            let formatted = format(src, options).unwrap();
+            assert_eq!(parse(&formatted).unwrap(), parse_with(src, options.lua).unwrap(), "{}", formatted);
+            assert_eq!(format(&formatted, options).unwrap(), formatted);
+        }
+    }
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_tokens() {
+        use serde_json::{from_str, to_string};
+        assert_eq!(to_string(&Token::Integer(42)).unwrap(), r#"{"Integer":42}"#);
+        assert_eq!(to_string(&Token::Keyword(Keyword::Local)).unwrap(), r#"{"Keyword":"Local"}"#);
+        assert_eq!(to_string(&Token::Comma).unwrap(), r#""Comma""#);
+        assert_eq!(to_string(&Token::StaticString(Cow::Borrowed(b"a\xff"))).unwrap(), r#"{"StaticString":[97,255]}"#);
+        assert_eq!(to_string(&Token::Float(f64::NAN)).unwrap(), r#"{"Float":"nan"}"#);
+        assert_eq!(from_str::<Token>(r#"{"Float":"-inf"}"#).unwrap(), Token::Float(f64::NEG_INFINITY));
+        let tokens = Lexer::new("x").collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(to_string(&tokens).unwrap(),
+                   r#"[[{"Ident":"x"},{"line":1,"pos":0},{"start":0,"end":1}]]"#);
+        let options = Options {
+            interpolation: true,
+            ..Options::default()
+        };
+        let src = "#!lua\nlocal s = 'a\\xff' .. $\"b{c + 1.5}\" -- done\nreturn 1e999, 0x10, 3.0";
+        let tokens = Lexer::with_options(src, options).collect::<Result<Vec<_>, _>>().unwrap();
+        let json = to_string(&tokens).unwrap();
+        assert_eq!(from_str::<Vec<Lexeme>>(&json).unwrap(), tokens);
+        assert!(json.contains(r#"{"Float":"inf"}"#), "{}", json);
+    }
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_ast() {
+        use serde_json::{from_str, to_string};
+        assert_eq!(to_string(&parse("return x").unwrap()).unwrap(),
+                   r#"[{"kind":{"Return":[{"kind":{"Name":{"symbol":"x","span":{"start":7,"end":8}}},"#.to_string() +
+                   r#""span":{"start":7,"end":8}}]},"span":{"start":0,"end":8}}]"#);
+        let src = "local a <const>, b = 1, 'b\\xff' function t.f:m(x, ...) return x .. ... end \
+                   for i = 1, 10 do t[i] = {i, -i, n = i * 2.5, [i] = not i} end \
+                   if a then goto l elseif b then repeat until #t > 0 else while a or b do break end end ::l::";
+        let block = parse(src).unwrap();
+        let json = to_string(&block).unwrap();
+        let back = from_str::<Block>(&json).unwrap();
+        assert_eq!(back, block);
+        // Spans are not compared by equality, so compare the serialized forms as well.
+        assert_eq!(to_string(&back).unwrap(), json);
     }
     #[test]
     fn lex_general() {
-        let src = format!("function Memoize(fn) fn = fn or function(x) return nil end return \
-                           setmetatable({{}}, {{ __index = function(t, k) local val = fn(k) t[k] \
-                           = val return val end, __call  = function(t, k) return t[k] end }}) end");
-        let mut lex = Lexer::new(&src);
+        let src = "function Memoize(fn) fn = fn or function(x) return nil end return \
+                   setmetatable({}, { __index = function(t, k) local val = fn(k) t[k] \
+                   = val return val end, __call  = function(t, k) return t[k] end }) end";
+        let mut lex = Lexer::new(src);
         matchseq!(lex,
                   Keyword::Function,
                   "Memoize",
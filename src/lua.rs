@@ -1,8 +1,15 @@
+#![allow(dead_code)]
+// Names mirror the constants of the reference implementation.
+#![allow(clippy::enum_variant_names)]
+
+//! Lua constants and operations.
+//! Shared by the parser and the runtime.
+
 /// Minimum Lua stack available to a C function.
 const LUA_MINSTACK: i32 = 20;
 
 /// Mark for precompiled code ('<esc>Lua').
-const LUA_SIGNATURE: &'static str = "\x1bLua";
+const LUA_SIGNATURE: &str = "\x1bLua";
 
 /// Thread error.
 enum ThreadError {
@@ -36,38 +43,72 @@ enum BasicType {
 }
 
 /// Bitwise operations.
-enum BitwiseOp {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BitwiseOp {
+    /// `&`.
     And,
+    /// `|`.
     Or,
+    /// Binary `~`.
     Xor,
+    /// Unary `~`.
     Not,
 }
 
 /// Arithmetic operations.
-enum ArithmeticOp {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ArithmeticOp {
+    /// `+`.
     Add,
+    /// Binary `-`.
     Sub,
+    /// `*`.
     Mul,
+    /// `%`.
     Mod,
+    /// `^`.
     Pow,
+    /// `/`.
     Div,
+    /// `//`.
     IDiv,
+    /// `<<`.
     Shl,
+    /// `>>`.
     Shr,
+    /// Unary `-`.
     Unm,
+    /// A bitwise operation.
     BitwiseOp(BitwiseOp),
 }
 
 /// Logical operations (comparisons).
-enum LogicalOp {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LogicalOp {
+    /// `==`.
     Equal,
+    /// `~=`.
+    NotEqual,
+    /// `<`.
     LessThan,
+    /// `<=`.
     LessThanOrEqual,
+    /// `>`.
+    GreaterThan,
+    /// `>=`.
+    GreaterThanOrEqual,
 }
 
 /// Operations.
-enum LuaOperation {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LuaOperation {
+    /// A comparison.
     Logical(LogicalOp),
+    /// An arithmetic or bitwise operation.
     Arithmetic(ArithmeticOp),
 }
 
@@ -3,35 +3,139 @@
 //! The semantic analyser.
 //! Performs semantic analysis on a set of lexical tokens.
 
-mod ast {
+pub mod ast {
     //! The abstract syntax tree.
 
+    use intern::Symbol;
+    use lexer::Span;
+    use lua::{ArithmeticOp, LuaOperation};
+    use visit::{walk_expr, walk_stmt, Visitor};
+
     /// Pseudo type for holding statements.
+    #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
     pub struct Block(pub Vec<Stmt>);
 
     /// Implements `Block`.
     impl Block {
+        /// Appends a statement.
         pub fn add_child(&mut self, stmt: Stmt) {
             self.0.push(stmt);
         }
     }
 
     /// A name for something.
-    pub struct Name(pub String);
+    /// Names are equal if their symbols are equal, wherever they appear in the source.
+    #[derive(Debug, Clone, Eq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct Name {
+        /// The name.
+        pub symbol: Symbol,
+        /// The location in the source.
+        pub span: Span,
+    }
+
+    /// Implements `PartialEq` for `Name`.
+    impl PartialEq for Name {
+        fn eq(&self, other: &Name) -> bool {
+            self.symbol == other.symbol
+        }
+    }
+
+    /// An attribute of a local variable.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub enum Attrib {
+        /// `<const>`, a variable that cannot be assigned to.
+        Const,
+        /// `<close>`, a constant variable whose value is closed when it goes out of scope.
+        Close,
+    }
+
+    /// The name of a function in a function statement.
+    /// # EBNF
+    /// ```plain
+    /// func_name = name {"." name} [":" name]
+    /// ```
+    #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct FuncName {
+        /// The name and the fields, e.g. `a`, `b` and `c` in `a.b.c`.
+        pub path: Vec<Name>,
+        /// The method name, e.g. `m` in `a.b:m`.
+        pub method: Option<Name>,
+    }
+
+    /// The parameters and the body of a function.
+    /// # EBNF
+    /// ```plain
+    /// func_body = "(" [name {"," name} ["," "..."] | "..."] ")" block "end"
+    /// ```
+    #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct FunctionBody {
+        /// The named parameters.
+        pub params: Vec<Name>,
+        /// Whether the function takes variable arguments.
+        pub varargs: bool,
+        /// The body.
+        pub block: Block,
+    }
+
+    /// A statement with its location in the source.
+    /// Spans are ignored when comparing statements, so that trees parsed from sources
+    /// which only differ in layout are equal.
+    #[derive(Debug, Clone)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct Stmt {
+        /// The kind of statement.
+        pub kind: StmtKind,
+        /// The location in the source.
+        pub span: Span,
+    }
+
+    /// Implements `Stmt`.
+    impl Stmt {
+        /// Constructs a new `Stmt`.
+        pub fn new(kind: StmtKind, span: Span) -> Stmt {
+            Stmt { kind, span }
+        }
+    }
+
+    /// Implements `PartialEq` for `Stmt`.
+    impl PartialEq for Stmt {
+        fn eq(&self, other: &Stmt) -> bool {
+            self.kind == other.kind
+        }
+    }
 
-    /// A statement.
-    pub enum Stmt {
+    /// The kind of a statement.
+    #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub enum StmtKind {
+        /// # EBNF
+        /// ```plain
+        /// do_stmt = "do" block "end"
+        /// ```
         Do(Block),
         /// # EBNF
         /// ```plain
-        /// set_stmt = name {"," name} "=" expr {"," expr}
+        /// set_stmt = lvalue {"," lvalue} "=" expr {"," expr}
         /// ```
         /// # Examples
         /// ```lua
         /// a = 0
-        /// b, c = a, a
+        /// b, t.c = a, a
         /// ```
-        Set(Vec<(Name, Expr)>),
+        /// Compound assignments like `t[f()] += 1` are lowered to this, inside a `Do` with locals
+        /// for the table and key of the target and its current value, see `Parser::lower_compound`.
+        Set(Vec<LValue>, Vec<Expr>),
+        /// An expression statement, which is always a function or method call.
+        /// # EBNF
+        /// ```plain
+        /// expr_stmt = call_expr
+        /// ```
+        Expr(Expr),
         /// # EBNF
         /// ```plain
         /// while_stmt = "while" expr "do" block "end"
@@ -44,7 +148,7 @@ mod ast {
         Repeat(Expr, Block),
         /// # EBNF
         /// ```plain
-        /// if_stmt = "if" expr "then" block {"else if" expr "then" block} ["else" block] end
+        /// if_stmt = "if" expr "then" block {"elseif" expr "then" block} ["else" block] "end"
         /// ```
         If(Vec<(Expr, Block)>, Option<Block>),
         /// # EBNF
@@ -56,25 +160,44 @@ mod ast {
         /// ```plain
         /// for_in_stmt = "for" name {"," name} "in" expr {"," expr} "do" block "end"
         /// ```
-        ForIn(Vec<(Name, Expr)>, Block),
+        ForIn(Vec<Name>, Vec<Expr>, Block),
+        /// # EBNF
+        /// ```plain
+        /// function_stmt = "function" func_name func_body
+        /// ```
+        /// # Examples
+        /// ```lua
+        /// function a.b:c(x) return x end
+        /// ```
+        Function(FuncName, FunctionBody),
         /// # EBNF
         /// ```plain
-        /// local_stmt = "local" name {"," name} "=" expr {"," expr}
+        /// local_function_stmt = "local" "function" name func_body
         /// ```
-        Local(Vec<(Name, Expr)>),
+        LocalFunction(Name, FunctionBody),
         /// # EBNF
         /// ```plain
-        /// goto_stmt = "goto" string
+        /// local_stmt = "local" name [attrib] {"," name [attrib]} ["=" expr {"," expr}]
+        /// attrib = "<" name ">"
+        /// ```
+        /// # Examples
+        /// ```lua
+        /// local a, b <const> = 1, 2
         /// ```
-        Goto(String),
+        Local(Vec<(Name, Option<Attrib>)>, Vec<Expr>),
         /// # EBNF
         /// ```plain
-        /// label_stmt = "::" string "::"
+        /// goto_stmt = "goto" name
         /// ```
-        Label(String),
+        Goto(Name),
         /// # EBNF
         /// ```plain
-        /// return_stmt = "return" expr {"," expr}
+        /// label_stmt = "::" name "::"
+        /// ```
+        Label(Name),
+        /// # EBNF
+        /// ```plain
+        /// return_stmt = "return" [expr {"," expr}] [";"]
         /// ```
         Return(Vec<Expr>),
         /// # EBNF
@@ -82,116 +205,1228 @@ mod ast {
         /// break_stmt = "break"
         /// ```
         Break,
+        /// A statement that could not be parsed.
+        /// Only produced when recovering from errors.
+        Error,
+    }
+
+    /// An expression with its location in the source.
+    /// Like for statements, spans are ignored when comparing.
+    #[derive(Debug, Clone)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct Expr {
+        /// The kind of expression.
+        pub kind: ExprKind,
+        /// The location in the source.
+        pub span: Span,
+    }
+
+    /// Implements `Expr`.
+    impl Expr {
+        /// Constructs a new `Expr`.
+        pub fn new(kind: ExprKind, span: Span) -> Expr {
+            Expr { kind, span }
+        }
     }
 
-    /// An expression.
-    pub enum Expr {
+    /// Implements `PartialEq` for `Expr`.
+    impl PartialEq for Expr {
+        fn eq(&self, other: &Expr) -> bool {
+            self.kind == other.kind
+        }
+    }
+
+    /// The kind of an expression.
+    #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub enum ExprKind {
+        /// `nil`.
         Nil,
+        /// `...`, the variable arguments of the enclosing function.
         Dots,
+        /// `true`.
         True,
+        /// `false`.
         False,
-        Number(f64),
-        StaticString(String),
-        Call(Name, Vec<Box<Expr>>),
+        /// An integer constant.
+        Integer(i64),
+        /// A floating-point constant.
+        Float(#[cfg_attr(feature = "serde", serde(with = "::serialize::float"))] f64),
+        /// A constant string.
+        StaticString(#[cfg_attr(feature = "serde", serde(with = "::serialize::bytes"))] Vec<u8>),
+        /// A variable.
+        Name(Name),
+        /// A parenthesized expression, which is truncated to a single value.
+        Paren(Box<Expr>),
+        /// A field access, e.g. `a.b`.
+        Field(Box<Expr>, Name),
+        /// An index operation, e.g. `a[b]`.
+        Index(Box<Expr>, Box<Expr>),
+        /// A function call, e.g. `f(a)`, `f "a"` or `f {a}`.
+        Call(Box<Expr>, Vec<Expr>),
+        /// A method call, e.g. `a:m(b)`.
+        MethodCall(Box<Expr>, Name, Vec<Expr>),
+        /// A function literal, e.g. `function(a) end`, or a lambda if the extension is enabled.
+        Function(FunctionBody),
+        /// A table constructor, e.g. `{a, b = c, [d] = e}`.
+        Table(TableConstructor),
+        /// An arithmetic, bitwise or comparison operation, e.g. `a + b` or `a < b`.
+        Operation(LuaOperation, Box<Expr>, Box<Expr>),
+        /// A unary minus or bitwise not, e.g. `-a` or `~a`.
+        Unary(ArithmeticOp, Box<Expr>),
+        /// A concatenation, e.g. `a .. b`.
+        Concat(Box<Expr>, Box<Expr>),
+        /// A logical negation, e.g. `not a`.
+        Not(Box<Expr>),
+        /// A length operation, e.g. `#a`.
+        Len(Box<Expr>),
+        /// A short-circuiting conjunction, e.g. `a and b`.
+        And(Box<Expr>, Box<Expr>),
+        /// A short-circuiting disjunction, e.g. `a or b`.
+        Or(Box<Expr>, Box<Expr>),
+    }
+
+    /// The target of an assignment.
+    #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub enum LValue {
+        /// A variable.
+        Name(Name),
+        /// A field, e.g. `a.b`.
+        Field(Expr, Name),
+        /// An indexed value, e.g. `a[b]`.
+        Index(Expr, Expr),
+    }
+
+    /// A table constructor.
+    /// # EBNF
+    /// ```plain
+    /// table = "{" [field {("," | ";") field} ["," | ";"]] "}"
+    /// ```
+    #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct TableConstructor(pub Vec<TableField>);
+
+    /// A field in a table constructor.
+    #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub enum TableField {
+        /// A value at the next integer key, e.g. `a`.
+        Positional(Expr),
+        /// A value at a name, e.g. `a = b`.
+        Named(Name, Expr),
+        /// A value at a computed key, e.g. `[a] = b`.
+        Bracketed(Expr, Expr),
+    }
+
+    /// A binary operator.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub enum BinaryOp {
+        /// An arithmetic, bitwise or comparison operator.
+        Operation(LuaOperation),
+        /// `..`.
+        Concat,
+        /// `and`.
+        And,
+        /// `or`.
+        Or,
+    }
+
+    /// A reference to a statement, an expression or a name.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Node<'a> {
+        /// A statement.
+        Stmt(&'a Stmt),
+        /// An expression.
+        Expr(&'a Expr),
+        /// A name.
+        Name(&'a Name),
+    }
+
+    /// Implements `Node`.
+    impl<'a> Node<'a> {
+        /// Gets the location in the source.
+        pub fn span(self) -> Span {
+            match self {
+                Node::Stmt(stmt) => stmt.span,
+                Node::Expr(expr) => expr.span,
+                Node::Name(name) => name.span,
+            }
+        }
+        /// Gets the statements, expressions and names directly contained in the node.
+        pub fn children(self) -> Vec<Node<'a>> {
+            let mut children = Children(Vec::new());
+            match self {
+                Node::Stmt(stmt) => walk_stmt(&mut children, stmt),
+                Node::Expr(expr) => walk_expr(&mut children, expr),
+                Node::Name(_) => (),
+            }
+            children.0
+        }
+    }
+
+    /// Collects the statements, expressions and names directly contained in a node.
+    struct Children<'a>(Vec<Node<'a>>);
+
+    /// Implements `Visitor` for `Children`.
+    impl<'a> Visitor<'a> for Children<'a> {
+        fn visit_stmt(&mut self, stmt: &'a Stmt) {
+            self.0.push(Node::Stmt(stmt));
+        }
+        fn visit_expr(&mut self, expr: &'a Expr) {
+            self.0.push(Node::Expr(expr));
+        }
+        fn visit_name(&mut self, name: &'a Name) {
+            self.0.push(Node::Name(name));
+        }
+    }
+
+    /// Implements `Block`.
+    impl Block {
+        /// Finds the innermost statement, expression or name that contains the byte at `offset`.
+        pub fn node_at(&self, offset: usize) -> Option<Node<'_>> {
+            let mut found = None;
+            let mut nodes: Vec<Node> = self.0.iter().map(Node::Stmt).collect();
+            while let Some(node) = nodes.into_iter().find(|node| node.span().contains(offset)) {
+                nodes = node.children();
+                found = Some(node);
+            }
+            found
+        }
     }
 }
 
-use lexer::Lexeme;
+use std::error;
+use std::fmt;
+use std::mem;
+use dialect::{Dialect, Options};
+use intern::Symbol;
+use lexer::{Lexeme, Span, TokenPosition};
+use lua::{ArithmeticOp, BitwiseOp, LogicalOp, LuaOperation};
 use parser::ast::*;
+use token::{Fragment, Keyword, Token};
+use visit::Visitor;
+
+/// Collects the symbols of the names in a node.
+struct Names(Vec<Symbol>);
 
-/// AstVisitor trait.
-/// Provides visitors for the AST.
-#[allow(missing_docs)]
-trait AstVisitor {
-    fn visit_name(&mut self, val: &Name);
+/// Implements `Visitor` for `Names`.
+impl<'a> Visitor<'a> for Names {
+    fn visit_name(&mut self, name: &'a Name) {
+        self.0.push(name.symbol.clone());
+    }
 }
 
-/// Cursor.
-struct Cursor {
-    pos: usize,
-    max_pos: usize,
+/// The priority of unary operators.
+/// Only `^` binds tighter, so `-a ^ b` is `-(a ^ b)`.
+pub(crate) const UNARY_PRIORITY: u8 = 12;
+
+/// Implements `BinaryOp`.
+impl BinaryOp {
+    /// Gets the binary operator for a token, if any.
+    fn from_token(token: &Token) -> Option<BinaryOp> {
+        let arith = |op| BinaryOp::Operation(LuaOperation::Arithmetic(op));
+        let bitwise = |op| arith(ArithmeticOp::BitwiseOp(op));
+        let logical = |op| BinaryOp::Operation(LuaOperation::Logical(op));
+        Some(match *token {
+            Token::Add => arith(ArithmeticOp::Add),
+            Token::SubOrMinus => arith(ArithmeticOp::Sub),
+            Token::Mul => arith(ArithmeticOp::Mul),
+            Token::Div => arith(ArithmeticOp::Div),
+            Token::FloorDiv => arith(ArithmeticOp::IDiv),
+            Token::Mod => arith(ArithmeticOp::Mod),
+            Token::Power => arith(ArithmeticOp::Pow),
+            Token::ShiftLeft => arith(ArithmeticOp::Shl),
+            Token::ShiftRight => arith(ArithmeticOp::Shr),
+            Token::BitAnd => bitwise(BitwiseOp::And),
+            Token::BitOr => bitwise(BitwiseOp::Or),
+            Token::BitXorOrNot => bitwise(BitwiseOp::Xor),
+            Token::Equal => logical(LogicalOp::Equal),
+            Token::NotEqual => logical(LogicalOp::NotEqual),
+            Token::LessThan => logical(LogicalOp::LessThan),
+            Token::LessThanEqual => logical(LogicalOp::LessThanOrEqual),
+            Token::GreaterThan => logical(LogicalOp::GreaterThan),
+            Token::GreaterThanEqual => logical(LogicalOp::GreaterThanOrEqual),
+            Token::Concat => BinaryOp::Concat,
+            Token::Keyword(Keyword::And) => BinaryOp::And,
+            Token::Keyword(Keyword::Or) => BinaryOp::Or,
+            _ => return None,
+        })
+    }
+    /// Gets the binary operator for a compound assignment token, if any.
+    fn from_compound_token(token: &Token) -> Option<BinaryOp> {
+        BinaryOp::from_token(&match *token {
+            Token::AddAssign => Token::Add,
+            Token::SubAssign => Token::SubOrMinus,
+            Token::MulAssign => Token::Mul,
+            Token::DivAssign => Token::Div,
+            Token::FloorDivAssign => Token::FloorDiv,
+            Token::ModAssign => Token::Mod,
+            Token::PowerAssign => Token::Power,
+            Token::ConcatAssign => Token::Concat,
+            Token::BitAndAssign => Token::BitAnd,
+            Token::BitOrAssign => Token::BitOr,
+            Token::ShiftLeftAssign => Token::ShiftLeft,
+            Token::ShiftRightAssign => Token::ShiftRight,
+            _ => return None,
+        })
+    }
+    /// Gets the left and right priority.
+    /// Right-associative operators bind less tightly on their right side.
+    pub(crate) fn priority(self) -> (u8, u8) {
+        match self {
+            BinaryOp::Or => (1, 1),
+            BinaryOp::And => (2, 2),
+            BinaryOp::Operation(LuaOperation::Logical(_)) => (3, 3),
+            BinaryOp::Operation(LuaOperation::Arithmetic(op)) => {
+                match op {
+                    ArithmeticOp::BitwiseOp(BitwiseOp::Or) => (4, 4),
+                    ArithmeticOp::BitwiseOp(BitwiseOp::Xor) => (5, 5),
+                    ArithmeticOp::BitwiseOp(_) => (6, 6),
+                    ArithmeticOp::Shl | ArithmeticOp::Shr => (7, 7),
+                    ArithmeticOp::Add | ArithmeticOp::Sub => (10, 10),
+                    ArithmeticOp::Pow => (14, 13),
+                    _ => (11, 11),
+                }
+            }
+            BinaryOp::Concat => (9, 8),
+        }
+    }
+    /// Builds the expression for the operation.
+    fn apply(self, lhs: Expr, rhs: Expr, span: Span) -> Expr {
+        let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+        let kind = match self {
+            BinaryOp::Operation(op) => ExprKind::Operation(op, lhs, rhs),
+            BinaryOp::Concat => ExprKind::Concat(lhs, rhs),
+            BinaryOp::And => ExprKind::And(lhs, rhs),
+            BinaryOp::Or => ExprKind::Or(lhs, rhs),
+        };
+        Expr::new(kind, span)
+    }
 }
 
-macro_rules! min {
-    ($a:expr,$b:expr) => {{
-        let a = $a;
-        let b = $b;
-        if a < b { a } else { b }
-    }}
+/// The kind of a syntax error.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    /// A token other than the expected one, or the end of the stream if `None`.
+    Expected(&'static str, Option<Token<'static>>),
+    /// A token that cannot appear at this point, or the end of the stream if `None`.
+    Unexpected(Option<Token<'static>>),
+    /// An attribute other than `const` or `close`.
+    UnknownAttribute(Symbol),
+    /// More than one `<close>` variable in a single local statement.
+    MultipleToBeClosed,
+    /// A `...` expression outside of a function that takes variable arguments.
+    VarArgsOutsideVarArgFunction,
+    /// A construct that is not supported by the selected dialect or options.
+    Unsupported(&'static str, Dialect),
 }
 
-/// Implements `Cursor`.
-impl Cursor {
-    /// Constructs a new `Cursor`.
-    fn new(max_pos: usize) -> Cursor {
-        Cursor {
-            pos: 0,
-            max_pos: max_pos,
-        }
+/// Writes a token for an error message, or `<eof>` for the end of the stream.
+fn write_near(f: &mut fmt::Formatter, token: &Option<Token>) -> fmt::Result {
+    match *token {
+        Some(ref token) => write!(f, "near `{}`", token),
+        None => write!(f, "near <eof>"),
     }
-    #[inline(always)]
-    fn next(&mut self) {
-        self.pos = min!(self.pos + 1, self.max_pos);
+}
+
+/// Implements `Display` for `ParseErrorKind`.
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseErrorKind::Expected(expected, ref token) => {
+                write!(f, "{} expected ", expected)?;
+                write_near(f, token)
+            }
+            ParseErrorKind::Unexpected(ref token) => {
+                write!(f, "unexpected symbol ")?;
+                write_near(f, token)
+            }
+            ParseErrorKind::UnknownAttribute(ref name) => write!(f, "unknown attribute `{}`", name),
+            ParseErrorKind::MultipleToBeClosed => write!(f, "multiple to-be-closed variables in local list"),
+            ParseErrorKind::VarArgsOutsideVarArgFunction => write!(f, "cannot use `...` outside a vararg function"),
+            ParseErrorKind::Unsupported(construct, dialect) => write!(f, "{} is not supported in {}", construct, dialect),
+        }
     }
-    #[inline(always)]
-    fn skip(&mut self, n: usize) {
-        self.pos = min!(self.pos + n, self.max_pos);
+}
+
+/// A syntax error with positional information.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The kind of error.
+    pub kind: ParseErrorKind,
+    /// The position of the token at which the error was detected.
+    pub pos: TokenPosition,
+}
+
+/// Implements `Display` for `ParseError`.
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.pos, self.kind)
     }
 }
 
+/// Implements `Error` for `ParseError`.
+impl error::Error for ParseError {}
+
 /// Parsing unit.
-struct ParsingUnit<'a, T: 'a> {
+/// A cursor over the tokens that skips comments and the hashbang.
+struct ParsingUnit<'a> {
     pos: usize,
-    size: usize,
-    tokens: &'a [T],
+    tokens: Vec<&'a Lexeme<'a>>,
 }
 
 /// Implements `ParsingUnit`.
-impl<'a, T> ParsingUnit<'a, T> {
+impl<'a> ParsingUnit<'a> {
     /// Constructs a new `ParsingUnit`.
-    fn new(items: &'a [T]) -> ParsingUnit<'a, T> {
-        ParsingUnit {
-            pos: 0,
-            size: items.len(),
-            tokens: items,
+    fn new(items: &'a [Lexeme<'a>]) -> ParsingUnit<'a> {
+        let tokens = items
+            .iter()
+            .filter(|lexeme| !matches!(lexeme.0, Token::Comment(_) | Token::Hashbang(_)))
+            .collect();
+        ParsingUnit { pos: 0, tokens }
+    }
+    /// Peeks at a token, `n` tokens ahead of the current one.
+    fn peek(&self, n: usize) -> Option<&'a Token<'a>> {
+        self.tokens.get(self.pos + n).map(|lexeme| &lexeme.0)
+    }
+    /// Advances to the next token.
+    fn skip(&mut self) {
+        self.pos = (self.pos + 1).min(self.tokens.len());
+    }
+    /// Determines whether the current token is on a later line than the previous one.
+    fn starts_line(&self) -> bool {
+        match (self.pos.checked_sub(1).and_then(|pos| self.tokens.get(pos)), self.tokens.get(self.pos)) {
+            (Some(prev), Some(cur)) => cur.1.line() > prev.1.line(),
+            (None, _) => true,
+            (_, None) => false,
+        }
+    }
+    /// Gets the span of the current token, or an empty span after the last token at the end of the stream.
+    fn span(&self) -> Span {
+        match self.tokens.get(self.pos) {
+            Some(lexeme) => lexeme.2,
+            None => {
+                let end = self.tokens.last().map_or(0, |lexeme| lexeme.2.end);
+                Span { start: end, end }
+            }
+        }
+    }
+    /// Gets the span from `start` to the end of the last token that was skipped.
+    fn span_from(&self, start: usize) -> Span {
+        let end = match self.pos {
+            0 => start,
+            pos => self.tokens[pos - 1].2.end,
+        };
+        Span {
+            start,
+            end: end.max(start),
         }
     }
-    fn see(self, lookahead: usize) -> bool {
-        self.pos + lookahead < self.size
+    /// Gets the position of the current token, or of the last token at the end of the stream.
+    fn position(&self) -> TokenPosition {
+        self.tokens
+            .get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map(|lexeme| lexeme.1)
+            .unwrap_or_default()
     }
 }
 
 /// Semantic analyser.
 pub struct Parser<'a> {
-    src: ParsingUnit<'a, Lexeme>,
+    src: ParsingUnit<'a>,
+    options: Options,
+    /// Whether `...` may be used in the current function.
+    varargs: bool,
+    /// The nesting depth of blocks, which is 1 in the main chunk.
+    depth: usize,
+    /// The errors that have been recovered from.
+    errors: Vec<ParseError>,
 }
 
 /// Implements `Parser`.
 impl<'a> Parser<'a> {
     /// Constructs a new `Parser`.
-    pub fn new(tokens: &'a Vec<Lexeme>) -> Parser<'a> {
-        Parser { src: ParsingUnit::new(tokens) }
+    pub fn new(tokens: &'a [Lexeme<'a>]) -> Parser<'a> {
+        Parser::with_options(tokens, Options::default())
+    }
+    /// Constructs a new `Parser` that accepts the given dialect and extensions.
+    /// The tokens should have been produced by a `Lexer` with the same options.
+    pub fn with_options(tokens: &'a [Lexeme<'a>], options: Options) -> Parser<'a> {
+        Parser {
+            src: ParsingUnit::new(tokens),
+            options,
+            varargs: true,
+            depth: 0,
+            errors: Vec::new(),
+        }
     }
     /// Analyses the semantics of a set of lexical tokens.
-    pub fn parse(&mut self) -> Block {
-        macro_rules! see {
-            () => (see!(1usize));
-            ($lookahead:expr) => (self.src.pos + usize::from($lookahead) < self.src.size);
+    /// The tokens form the main chunk, which takes variable arguments.
+    /// Returns the first error, if any.
+    pub fn parse(&mut self) -> Result<Block, ParseError> {
+        let (block, mut errors) = self.parse_recovering();
+        match errors.is_empty() {
+            true => Ok(block),
+            false => Err(errors.swap_remove(0)),
         }
-        let mut root = Block(vec![]);
-        while see!() {
-            root.add_child({
-                // Statement
-                unimplemented!()
-            });
+    }
+    /// Analyses the semantics of a set of lexical tokens, recovering from errors.
+    /// Statements that cannot be parsed are replaced with `StmtKind::Error`,
+    /// and parsing resumes at the start of the next statement.
+    /// Returns the partial block and all errors in source order.
+    pub fn parse_recovering(&mut self) -> (Block, Vec<ParseError>) {
+        self.varargs = true;
+        let mut block = self.parse_block();
+        while self.src.peek(0).is_some() {
+            // An `end`, `else`, `elseif` or `until` that does not close anything,
+            // or a statement after a return statement.
+            let (start, offset) = (self.src.pos, self.src.span().start);
+            let err = self.expected("<eof>");
+            self.recover(err, start);
+            block.add_child(Stmt::new(StmtKind::Error, self.src.span_from(offset)));
+            block.0.extend(self.parse_block().0);
         }
-        root
+        (block, mem::take(&mut self.errors))
+    }
+
+    /// Creates an error at the current token.
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            kind,
+            pos: self.src.position(),
+        }
+    }
+    /// Creates an error for a missing token at the current token.
+    fn expected(&self, expected: &'static str) -> ParseError {
+        let found = self.src.peek(0).map(|token| token.clone().into_owned());
+        self.error(ParseErrorKind::Expected(expected, found))
+    }
+    /// Creates an error for an unexpected current token.
+    fn unexpected(&self) -> ParseError {
+        let found = self.src.peek(0).map(|token| token.clone().into_owned());
+        self.error(ParseErrorKind::Unexpected(found))
+    }
+    /// Determines whether the current token is the given one.
+    fn check(&self, token: &Token) -> bool {
+        self.src.peek(0) == Some(token)
+    }
+    /// Skips the current token if it is the given one.
+    fn accept(&mut self, token: &Token) -> bool {
+        let found = self.check(token);
+        if found {
+            self.src.skip();
+        }
+        found
+    }
+    /// Skips the current token, which has to be the given one.
+    fn expect(&mut self, token: &Token, expected: &'static str) -> Result<(), ParseError> {
+        if self.accept(token) {
+            Ok(())
+        } else {
+            Err(self.expected(expected))
+        }
+    }
+    /// Skips the token closing a construct, e.g. the `end` of a `while`.
+    /// The error is reported at the opening token if they are on different lines.
+    fn expect_match(&mut self, token: &Token, expected: &'static str, opened: TokenPosition) -> Result<(), ParseError> {
+        if self.accept(token) {
+            return Ok(());
+        }
+        let mut err = self.expected(expected);
+        if opened.line() != err.pos.line() {
+            err.pos = opened;
+        }
+        Err(err)
+    }
+    /// Reads a name.
+    fn expect_name(&mut self) -> Result<Name, ParseError> {
+        match self.src.peek(0) {
+            Some(Token::Ident(symbol)) => {
+                let span = self.src.span();
+                self.src.skip();
+                Ok(Name { symbol: symbol.clone(), span })
+            }
+            _ => Err(self.expected("name")),
+        }
+    }
+    /// Determines whether the current token ends a block.
+    fn block_follow(&self) -> bool {
+        matches!(self.src.peek(0),
+                 None |
+                 Some(&Token::Keyword(Keyword::End)) |
+                 Some(&Token::Keyword(Keyword::Else)) |
+                 Some(&Token::Keyword(Keyword::ElseIf)) |
+                 Some(&Token::Keyword(Keyword::Until)))
     }
-}
 
-#[allow(unused_variables)]
-impl<'a> AstVisitor for Parser<'a> {
-    fn visit_name(&mut self, val: &Name) {
-        unimplemented!()
+    /// Records an error and skips to the start of the next statement.
+    /// `start` is the index of the first token of the statement that failed.
+    fn recover(&mut self, err: ParseError, start: usize) {
+        self.errors.push(err);
+        if self.src.pos == start {
+            self.src.skip();
+        }
+        while let Some(token) = self.src.peek(0) {
+            match *token {
+                Token::Semicolon |
+                Token::DoubleColon |
+                Token::Keyword(Keyword::Break) |
+                Token::Keyword(Keyword::Do) |
+                Token::Keyword(Keyword::For) |
+                Token::Keyword(Keyword::Function) |
+                Token::Keyword(Keyword::Goto) |
+                Token::Keyword(Keyword::If) |
+                Token::Keyword(Keyword::Local) |
+                Token::Keyword(Keyword::Repeat) |
+                Token::Keyword(Keyword::Return) |
+                Token::Keyword(Keyword::While) => break,
+                // Assignments and calls start with a name or a parenthesis,
+                // which most likely start a statement if they start a line.
+                Token::Ident(_) |
+                Token::OpenParen if self.src.starts_line() => break,
+                // Tokens that end a block only synchronize in nested blocks,
+                // where they are likely to close the construct containing the error.
+                Token::Keyword(Keyword::End) |
+                Token::Keyword(Keyword::Else) |
+                Token::Keyword(Keyword::ElseIf) |
+                Token::Keyword(Keyword::Until) if self.depth > 1 => break,
+                _ => self.src.skip(),
+            }
+        }
     }
-}
\ No newline at end of file
+
+    /// Parses statements up to the end of a block, recovering from errors.
+    /// # EBNF
+    /// ```plain
+    /// block = {stmt} [return_stmt]
+    /// ```
+    fn parse_block(&mut self) -> Block {
+        self.depth += 1;
+        let mut block = Block(Vec::new());
+        while !self.block_follow() {
+            let (start, offset) = (self.src.pos, self.src.span().start);
+            // A return statement has to be the last statement of its block.
+            let last = self.check(&Token::Keyword(Keyword::Return));
+            match self.parse_stmt() {
+                Ok(Some(stmt)) => block.add_child(stmt),
+                Ok(None) => (),
+                Err(err) => {
+                    self.recover(err, start);
+                    block.add_child(Stmt::new(StmtKind::Error, self.src.span_from(offset)));
+                }
+            }
+            if last {
+                break;
+            }
+        }
+        self.depth -= 1;
+        block
+    }
+
+    /// Parses a statement.
+    /// Returns `None` for empty statements.
+    fn parse_stmt(&mut self) -> Result<Option<Stmt>, ParseError> {
+        let pos = self.src.position();
+        let start = self.src.span().start;
+        let token = match self.src.peek(0) {
+            Some(token) => token,
+            None => return Err(self.unexpected()),
+        };
+        let kind = match *token {
+            Token::Semicolon => {
+                self.src.skip();
+                return Ok(None);
+            }
+            Token::DoubleColon => {
+                self.src.skip();
+                let name = self.expect_name()?;
+                self.expect(&Token::DoubleColon, "`::`")?;
+                StmtKind::Label(name)
+            }
+            Token::Keyword(Keyword::Break) => {
+                self.src.skip();
+                StmtKind::Break
+            }
+            Token::Keyword(Keyword::Goto) => {
+                self.src.skip();
+                StmtKind::Goto(self.expect_name()?)
+            }
+            Token::Keyword(Keyword::Do) => {
+                self.src.skip();
+                let block = self.parse_block();
+                self.expect_match(&Token::Keyword(Keyword::End), "`end`", pos)?;
+                StmtKind::Do(block)
+            }
+            Token::Keyword(Keyword::While) => {
+                self.src.skip();
+                let cond = self.parse_expr()?;
+                self.expect(&Token::Keyword(Keyword::Do), "`do`")?;
+                let block = self.parse_block();
+                self.expect_match(&Token::Keyword(Keyword::End), "`end`", pos)?;
+                StmtKind::While(cond, block)
+            }
+            Token::Keyword(Keyword::Repeat) => {
+                self.src.skip();
+                let block = self.parse_block();
+                self.expect_match(&Token::Keyword(Keyword::Until), "`until`", pos)?;
+                let cond = self.parse_expr()?;
+                StmtKind::Repeat(cond, block)
+            }
+            Token::Keyword(Keyword::Return) => self.parse_return()?,
+            Token::Keyword(Keyword::If) => self.parse_if()?,
+            Token::Keyword(Keyword::For) => self.parse_for()?,
+            Token::Keyword(Keyword::Function) => {
+                self.src.skip();
+                let mut path = vec![self.expect_name()?];
+                while self.accept(&Token::MemberAccess) {
+                    path.push(self.expect_name()?);
+                }
+                let method = match self.accept(&Token::Colon) {
+                    true => Some(self.expect_name()?),
+                    false => None,
+                };
+                let body = self.parse_function_body(pos)?;
+                StmtKind::Function(FuncName { path, method }, body)
+            }
+            Token::Keyword(Keyword::Local) => {
+                self.src.skip();
+                if self.accept(&Token::Keyword(Keyword::Function)) {
+                    let name = self.expect_name()?;
+                    StmtKind::LocalFunction(name, self.parse_function_body(pos)?)
+                } else {
+                    self.parse_local()?
+                }
+            }
+            _ => self.parse_expr_stmt()?,
+        };
+        Ok(Some(Stmt::new(kind, self.src.span_from(start))))
+    }
+
+    /// Parses a return statement.
+    fn parse_return(&mut self) -> Result<StmtKind, ParseError> {
+        self.src.skip();
+        let exprs = match self.block_follow() || self.check(&Token::Semicolon) {
+            true => Vec::new(),
+            false => self.parse_expr_list()?,
+        };
+        self.accept(&Token::Semicolon);
+        Ok(StmtKind::Return(exprs))
+    }
+
+    /// Parses an if statement.
+    fn parse_if(&mut self) -> Result<StmtKind, ParseError> {
+        let pos = self.src.position();
+        let mut branches = Vec::new();
+        loop {
+            // Skip the `if` or `elseif`.
+            self.src.skip();
+            let cond = self.parse_expr()?;
+            self.expect(&Token::Keyword(Keyword::Then), "`then`")?;
+            branches.push((cond, self.parse_block()));
+            if !self.check(&Token::Keyword(Keyword::ElseIf)) {
+                break;
+            }
+        }
+        let otherwise = match self.accept(&Token::Keyword(Keyword::Else)) {
+            true => Some(self.parse_block()),
+            false => None,
+        };
+        self.expect_match(&Token::Keyword(Keyword::End), "`end`", pos)?;
+        Ok(StmtKind::If(branches, otherwise))
+    }
+
+    /// Parses a numeric or generic for statement.
+    fn parse_for(&mut self) -> Result<StmtKind, ParseError> {
+        let pos = self.src.position();
+        self.src.skip();
+        let name = self.expect_name()?;
+        let kind = if self.accept(&Token::Assignment) {
+            let start = self.parse_expr()?;
+            self.expect(&Token::Comma, "`,`")?;
+            let end = self.parse_expr()?;
+            let step = match self.accept(&Token::Comma) {
+                true => Some(self.parse_expr()?),
+                false => None,
+            };
+            self.expect(&Token::Keyword(Keyword::Do), "`do`")?;
+            let block = self.parse_block();
+            StmtKind::ForNum(name, start, end, step, block)
+        } else if self.check(&Token::Comma) || self.check(&Token::Keyword(Keyword::In)) {
+            let mut names = vec![name];
+            while self.accept(&Token::Comma) {
+                names.push(self.expect_name()?);
+            }
+            self.expect(&Token::Keyword(Keyword::In), "`in`")?;
+            let exprs = self.parse_expr_list()?;
+            self.expect(&Token::Keyword(Keyword::Do), "`do`")?;
+            let block = self.parse_block();
+            StmtKind::ForIn(names, exprs, block)
+        } else {
+            return Err(self.expected("`=` or `in`"));
+        };
+        self.expect_match(&Token::Keyword(Keyword::End), "`end`", pos)?;
+        Ok(kind)
+    }
+
+    /// Parses a local statement after the `local`.
+    fn parse_local(&mut self) -> Result<StmtKind, ParseError> {
+        let mut names = Vec::new();
+        let mut closed = false;
+        loop {
+            let name = self.expect_name()?;
+            let attrib = self.parse_attrib()?;
+            if attrib == Some(Attrib::Close) {
+                if closed {
+                    return Err(self.error(ParseErrorKind::MultipleToBeClosed));
+                }
+                closed = true;
+            }
+            names.push((name, attrib));
+            if !self.accept(&Token::Comma) {
+                break;
+            }
+        }
+        let exprs = match self.accept(&Token::Assignment) {
+            true => self.parse_expr_list()?,
+            false => Vec::new(),
+        };
+        Ok(StmtKind::Local(names, exprs))
+    }
+
+    /// Parses the attribute of a local variable, if any.
+    fn parse_attrib(&mut self) -> Result<Option<Attrib>, ParseError> {
+        if !self.check(&Token::LessThan) {
+            return Ok(None);
+        }
+        if self.options.dialect < Dialect::Lua54 {
+            return Err(self.error(ParseErrorKind::Unsupported("attributes", self.options.dialect)));
+        }
+        self.src.skip();
+        let pos = self.src.position();
+        let name = self.expect_name()?.symbol;
+        self.expect(&Token::GreaterThan, "`>`")?;
+        match name.as_bytes() {
+            b"const" => Ok(Some(Attrib::Const)),
+            b"close" => Ok(Some(Attrib::Close)),
+            _ => {
+                Err(ParseError {
+                    kind: ParseErrorKind::UnknownAttribute(name),
+                    pos,
+                })
+            }
+        }
+    }
+
+    /// Parses the parameters and the body of a function.
+    /// `pos` is the position of the statement or expression that the function belongs to.
+    fn parse_function_body(&mut self, pos: TokenPosition) -> Result<FunctionBody, ParseError> {
+        self.expect(&Token::OpenParen, "`(`")?;
+        let (params, varargs) = self.parse_params(&Token::CloseParen)?;
+        self.expect(&Token::CloseParen, "`)`")?;
+        let outer = self.varargs;
+        self.varargs = varargs;
+        let block = self.parse_block();
+        self.varargs = outer;
+        self.expect_match(&Token::Keyword(Keyword::End), "`end`", pos)?;
+        Ok(FunctionBody {
+            params,
+            varargs,
+            block,
+        })
+    }
+
+    /// Parses a parameter list up to the given closing token.
+    /// Returns the named parameters and whether the function takes variable arguments.
+    fn parse_params(&mut self, close: &Token) -> Result<(Vec<Name>, bool), ParseError> {
+        let mut params = Vec::new();
+        if self.check(close) {
+            return Ok((params, false));
+        }
+        loop {
+            if self.accept(&Token::VarArgs) {
+                return Ok((params, true));
+            }
+            params.push(self.expect_name()?);
+            if !self.accept(&Token::Comma) {
+                return Ok((params, false));
+            }
+        }
+    }
+
+    /// Parses an assignment or a function call statement.
+    fn parse_expr_stmt(&mut self) -> Result<StmtKind, ParseError> {
+        let start = self.src.span().start;
+        let expr = self.parse_suffixed_expr()?;
+        if let Some(op) = self.src.peek(0).and_then(BinaryOp::from_compound_token) {
+            let target = self.lvalue(expr)?;
+            self.check_bit_or()?;
+            self.src.skip();
+            let val = self.parse_expr()?;
+            return Ok(Parser::lower_compound(target, op, val, self.src.span_from(start)));
+        }
+        if !self.check(&Token::Assignment) && !self.check(&Token::Comma) {
+            return match expr.kind {
+                ExprKind::Call(..) | ExprKind::MethodCall(..) => Ok(StmtKind::Expr(expr)),
+                _ => Err(self.unexpected()),
+            };
+        }
+        let mut targets = vec![self.lvalue(expr)?];
+        while self.accept(&Token::Comma) {
+            let expr = self.parse_suffixed_expr()?;
+            targets.push(self.lvalue(expr)?);
+        }
+        self.expect(&Token::Assignment, "`=`")?;
+        Ok(StmtKind::Set(targets, self.parse_expr_list()?))
+    }
+
+    /// Lowers a compound assignment to an ordinary one.
+    /// Whatever the target, its table and key are evaluated once, then its current value is read,
+    /// and only then the value is evaluated, e.g. `a += f()` becomes `do local v = a a = v + f() end`
+    /// and `t[k] += f()` becomes `do local t, k = t, k local v = t[k] t[k] = v + f() end`.
+    /// The locals are renamed if the value or a variable target refer to their names, so that they never shadow them.
+    /// The table and key are evaluated before the locals are in scope, so they cannot refer to them.
+    /// The lowered nodes span the whole statement.
+    fn lower_compound(target: LValue, op: BinaryOp, val: Expr, span: Span) -> StmtKind {
+        let mut used = Names(Vec::new());
+        if let LValue::Name(ref name) = target {
+            used.visit_name(name);
+        }
+        used.visit_expr(&val);
+        let local = |text: &str| {
+            let mut text = text.to_string();
+            while used.0.iter().any(|symbol| symbol.as_bytes() == text.as_bytes()) {
+                text.push('_');
+            }
+            Name {
+                symbol: Symbol::from(&*text),
+                span,
+            }
+        };
+        let var = |name: &Name| Expr::new(ExprKind::Name(name.clone()), span);
+        let (table, key, current) = (local("t"), local("k"), local("v"));
+        let mut stmts = Vec::new();
+        let (target, read) = match target {
+            LValue::Name(name) => (LValue::Name(name.clone()), ExprKind::Name(name)),
+            LValue::Field(obj, name) => {
+                stmts.push(StmtKind::Local(vec![(table.clone(), None)], vec![obj]));
+                (LValue::Field(var(&table), name.clone()), ExprKind::Field(Box::new(var(&table)), name))
+            }
+            LValue::Index(obj, key_expr) => {
+                stmts.push(StmtKind::Local(vec![(table.clone(), None), (key.clone(), None)], vec![obj, key_expr]));
+                (LValue::Index(var(&table), var(&key)),
+                 ExprKind::Index(Box::new(var(&table)), Box::new(var(&key))))
+            }
+        };
+        stmts.push(StmtKind::Local(vec![(current.clone(), None)], vec![Expr::new(read, span)]));
+        stmts.push(StmtKind::Set(vec![target], vec![op.apply(var(&current), val, span)]));
+        StmtKind::Do(Block(stmts.into_iter().map(|kind| Stmt::new(kind, span)).collect()))
+    }
+
+    /// Converts an expression to the target of an assignment.
+    /// Only variables, fields and indexed values can be assigned to.
+    fn lvalue(&self, expr: Expr) -> Result<LValue, ParseError> {
+        match expr.kind {
+            ExprKind::Name(name) => Ok(LValue::Name(name)),
+            ExprKind::Field(table, name) => Ok(LValue::Field(*table, name)),
+            ExprKind::Index(table, key) => Ok(LValue::Index(*table, *key)),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Parses a comma-separated list of at least one expression.
+    fn parse_expr_list(&mut self) -> Result<Vec<Expr>, ParseError> {
+        let mut exprs = vec![self.parse_expr()?];
+        while self.accept(&Token::Comma) {
+            exprs.push(self.parse_expr()?);
+        }
+        Ok(exprs)
+    }
+
+    /// Parses an expression.
+    fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        self.parse_subexpr(0)
+    }
+
+    /// Parses an expression whose binary operators bind more tightly than `limit`.
+    /// # EBNF
+    /// ```plain
+    /// expr = (simple_expr | unop expr) {binop expr}
+    /// ```
+    fn parse_subexpr(&mut self, limit: u8) -> Result<Expr, ParseError> {
+        let start = self.src.span().start;
+        let unary = match self.src.peek(0) {
+            Some(&Token::Keyword(Keyword::Not)) => Some(ExprKind::Not(self.parse_operand()?)),
+            Some(&Token::Len) => Some(ExprKind::Len(self.parse_operand()?)),
+            Some(&Token::SubOrMinus) => Some(ExprKind::Unary(ArithmeticOp::Unm, self.parse_operand()?)),
+            Some(&Token::BitXorOrNot) => {
+                Some(ExprKind::Unary(ArithmeticOp::BitwiseOp(BitwiseOp::Not), self.parse_operand()?))
+            }
+            _ => None,
+        };
+        let mut lhs = match unary {
+            Some(kind) => Expr::new(kind, self.src.span_from(start)),
+            None => self.parse_simple_expr()?,
+        };
+        while let Some(op) = self.src.peek(0).and_then(BinaryOp::from_token) {
+            let (left, right) = op.priority();
+            if left <= limit {
+                break;
+            }
+            self.check_bit_or()?;
+            self.src.skip();
+            let rhs = self.parse_subexpr(right)?;
+            lhs = op.apply(lhs, rhs, self.src.span_from(start));
+        }
+        Ok(lhs)
+    }
+
+    /// Rejects `|` as a binary operator below Lua 5.3.
+    /// The lexer accepts it in every dialect if lambdas are enabled, since they are delimited by it.
+    fn check_bit_or(&self) -> Result<(), ParseError> {
+        match self.src.peek(0) {
+            Some(&Token::BitOr) | Some(&Token::BitOrAssign) if self.options.dialect < Dialect::Lua53 => {
+                Err(self.error(ParseErrorKind::Unsupported("`|`", self.options.dialect)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Parses the operand of a unary operator.
+    fn parse_operand(&mut self) -> Result<Box<Expr>, ParseError> {
+        self.src.skip();
+        Ok(Box::new(self.parse_subexpr(UNARY_PRIORITY)?))
+    }
+
+    /// Parses a literal or a suffixed expression.
+    fn parse_simple_expr(&mut self) -> Result<Expr, ParseError> {
+        let span = self.src.span();
+        let kind = match self.src.peek(0) {
+            Some(&Token::Keyword(Keyword::Nil)) => ExprKind::Nil,
+            Some(&Token::Keyword(Keyword::True)) => ExprKind::True,
+            Some(&Token::Keyword(Keyword::False)) => ExprKind::False,
+            Some(&Token::Integer(val)) => ExprKind::Integer(val),
+            Some(&Token::Float(val)) => ExprKind::Float(val),
+            Some(Token::StaticString(val)) => ExprKind::StaticString(val.to_vec()),
+            Some(Token::Interpolation(fragments)) => {
+                let expr = self.parse_interpolation(fragments, span)?;
+                self.src.skip();
+                return Ok(expr);
+            }
+            Some(&Token::OpenBrace) => {
+                let kind = ExprKind::Table(self.parse_table()?);
+                return Ok(Expr::new(kind, self.src.span_from(span.start)));
+            }
+            Some(&Token::BitOr) if self.options.lambda => {
+                let kind = ExprKind::Function(self.parse_lambda()?);
+                return Ok(Expr::new(kind, self.src.span_from(span.start)));
+            }
+            Some(&Token::Keyword(Keyword::Function)) => {
+                let pos = self.src.position();
+                self.src.skip();
+                let kind = ExprKind::Function(self.parse_function_body(pos)?);
+                return Ok(Expr::new(kind, self.src.span_from(span.start)));
+            }
+            Some(&Token::VarArgs) => {
+                if !self.varargs {
+                    return Err(self.error(ParseErrorKind::VarArgsOutsideVarArgFunction));
+                }
+                ExprKind::Dots
+            }
+            _ => return self.parse_suffixed_expr(),
+        };
+        self.src.skip();
+        Ok(Expr::new(kind, span))
+    }
+
+    /// Parses an interpolated string into a concatenation.
+    /// `$"a = {a}"` becomes `("a = " .. tostring(a))`, in parentheses so that it is a single operand
+    /// like other strings, e.g. in `$"a = {a}" .. b` or `#$"a = {a}"`.
+    /// The strings, the concatenations and the `tostring` calls span the whole interpolated string.
+    fn parse_interpolation(&mut self, fragments: &'a [Fragment<'a>], span: Span) -> Result<Expr, ParseError> {
+        let mut parts = Vec::new();
+        for fragment in fragments {
+            match *fragment {
+                Fragment::Text(ref val) if val.is_empty() => (),
+                Fragment::Text(ref val) => parts.push(Expr::new(ExprKind::StaticString(val.to_vec()), span)),
+                Fragment::Expr(ref lexemes) => {
+                    let mut parser = Parser {
+                        src: ParsingUnit::new(lexemes),
+                        options: self.options,
+                        varargs: self.varargs,
+                        depth: self.depth,
+                        errors: Vec::new(),
+                    };
+                    if parser.src.peek(0).is_none() {
+                        return Err(self.unexpected());
+                    }
+                    let expr = parser.parse_expr();
+                    // Errors recovered from in function literals belong to this parser.
+                    self.errors.append(&mut parser.errors);
+                    let expr = expr?;
+                    if parser.src.peek(0).is_some() {
+                        return Err(parser.expected("`}`"));
+                    }
+                    let tostring = Name {
+                        symbol: Symbol::from("tostring"),
+                        span,
+                    };
+                    let tostring = Expr::new(ExprKind::Name(tostring), span);
+                    parts.push(Expr::new(ExprKind::Call(Box::new(tostring), vec![expr]), span));
+                }
+            }
+        }
+        // `..` is right-associative, so the concatenation is built from the right.
+        let mut parts = parts.into_iter().rev();
+        let last = parts.next().unwrap_or_else(|| Expr::new(ExprKind::StaticString(Vec::new()), span));
+        Ok(match parts.len() {
+            0 => last,
+            _ => {
+                let concat = parts.fold(last, |rhs, lhs| {
+                    Expr::new(ExprKind::Concat(Box::new(lhs), Box::new(rhs)), span)
+                });
+                Expr::new(ExprKind::Paren(Box::new(concat)), span)
+            }
+        })
+    }
+
+    /// Parses a name or a parenthesized expression.
+    fn parse_primary_expr(&mut self) -> Result<Expr, ParseError> {
+        match self.src.peek(0) {
+            Some(&Token::Ident(_)) => {
+                let name = self.expect_name()?;
+                let span = name.span;
+                Ok(Expr::new(ExprKind::Name(name), span))
+            }
+            Some(&Token::OpenParen) => {
+                let (pos, start) = (self.src.position(), self.src.span().start);
+                self.src.skip();
+                let expr = self.parse_expr()?;
+                self.expect_match(&Token::CloseParen, "`)`", pos)?;
+                Ok(Expr::new(ExprKind::Paren(Box::new(expr)), self.src.span_from(start)))
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Parses a primary expression followed by any number of fields, indices and calls.
+    /// # EBNF
+    /// ```plain
+    /// suffixed_expr = primary_expr {"." name | "[" expr "]" | ":" name args | args}
+    /// ```
+    fn parse_suffixed_expr(&mut self) -> Result<Expr, ParseError> {
+        let start = self.src.span().start;
+        let mut expr = self.parse_primary_expr()?;
+        loop {
+            let kind = match self.src.peek(0) {
+                Some(&Token::MemberAccess) => {
+                    self.src.skip();
+                    ExprKind::Field(Box::new(expr), self.expect_name()?)
+                }
+                Some(&Token::OpenBracket) => {
+                    let pos = self.src.position();
+                    self.src.skip();
+                    let key = self.parse_expr()?;
+                    self.expect_match(&Token::CloseBracket, "`]`", pos)?;
+                    ExprKind::Index(Box::new(expr), Box::new(key))
+                }
+                Some(&Token::Colon) => {
+                    self.src.skip();
+                    let name = self.expect_name()?;
+                    ExprKind::MethodCall(Box::new(expr), name, self.parse_args()?)
+                }
+                Some(&Token::OpenParen) |
+                Some(&Token::OpenBrace) |
+                Some(&Token::StaticString(_)) => ExprKind::Call(Box::new(expr), self.parse_args()?),
+                _ => return Ok(expr),
+            };
+            expr = Expr::new(kind, self.src.span_from(start));
+        }
+    }
+
+    /// Parses the arguments of a call.
+    /// # EBNF
+    /// ```plain
+    /// args = "(" [expr {"," expr}] ")" | table | string
+    /// ```
+    fn parse_args(&mut self) -> Result<Vec<Expr>, ParseError> {
+        match self.src.peek(0) {
+            Some(&Token::OpenParen) => {
+                let pos = self.src.position();
+                self.src.skip();
+                let args = match self.check(&Token::CloseParen) {
+                    true => Vec::new(),
+                    false => self.parse_expr_list()?,
+                };
+                self.expect_match(&Token::CloseParen, "`)`", pos)?;
+                Ok(args)
+            }
+            Some(&Token::OpenBrace) => {
+                let start = self.src.span().start;
+                let kind = ExprKind::Table(self.parse_table()?);
+                Ok(vec![Expr::new(kind, self.src.span_from(start))])
+            }
+            Some(Token::StaticString(val)) => {
+                let span = self.src.span();
+                self.src.skip();
+                Ok(vec![Expr::new(ExprKind::StaticString(val.to_vec()), span)])
+            }
+            _ => Err(self.expected("function arguments")),
+        }
+    }
+
+    /// Parses a table constructor.
+    fn parse_table(&mut self) -> Result<TableConstructor, ParseError> {
+        let pos = self.src.position();
+        self.expect(&Token::OpenBrace, "`{`")?;
+        let mut fields = Vec::new();
+        while !self.check(&Token::CloseBrace) {
+            let field = match (self.src.peek(0), self.src.peek(1)) {
+                (Some(&Token::Ident(_)), Some(&Token::Assignment)) => {
+                    let name = self.expect_name()?;
+                    self.src.skip();
+                    TableField::Named(name, self.parse_expr()?)
+                }
+                (Some(&Token::OpenBracket), _) => {
+                    let pos = self.src.position();
+                    self.src.skip();
+                    let key = self.parse_expr()?;
+                    self.expect_match(&Token::CloseBracket, "`]`", pos)?;
+                    self.expect(&Token::Assignment, "`=`")?;
+                    TableField::Bracketed(key, self.parse_expr()?)
+                }
+                _ => TableField::Positional(self.parse_expr()?),
+            };
+            fields.push(field);
+            if !self.accept(&Token::Comma) && !self.accept(&Token::Semicolon) {
+                break;
+            }
+        }
+        self.expect_match(&Token::CloseBrace, "`}`", pos)?;
+        Ok(TableConstructor(fields))
+    }
+
+    /// Parses a lambda, which is a function that returns a single expression.
+    /// # EBNF
+    /// ```plain
+    /// lambda = "|" [name {"," name} ["," "..."] | "..."] "|" expr
+    /// ```
+    fn parse_lambda(&mut self) -> Result<FunctionBody, ParseError> {
+        self.expect(&Token::BitOr, "`|`")?;
+        let (params, varargs) = self.parse_params(&Token::BitOr)?;
+        self.expect(&Token::BitOr, "`|`")?;
+        let outer = self.varargs;
+        self.varargs = varargs;
+        let expr = self.parse_expr();
+        self.varargs = outer;
+        let expr = expr?;
+        let span = expr.span;
+        Ok(FunctionBody {
+            params,
+            varargs,
+            block: Block(vec![Stmt::new(StmtKind::Return(vec![expr]), span)]),
+        })
+    }
+}
@@ -0,0 +1,144 @@
+#![deny(dead_code)]
+#![deny(missing_docs)]
+
+//! Serialization of tokens and the AST.
+//! Only available with the `serde` feature.
+//!
+//! Tokens, lexemes and AST nodes use the default representation of serde, which looks like this in JSON:
+//!
+//! - Structs are objects keyed by field name, e.g. `{"start": 0, "end": 5}` for a `Span`,
+//!   `{"line": 1, "pos": 0}` for a `TokenPosition` and `{"kind": ..., "span": ...}` for a `Stmt` or `Expr`.
+//! - Tuple structs are arrays, e.g. `[token, position, span]` for a `Lexeme`.
+//!   Structs with a single unnamed field are their field, e.g. an array of statements for a `Block`.
+//! - Variants without fields are strings, e.g. `"Nil"` or `"Comma"`.
+//! - Other variants are objects with the variant name as the only key, e.g. `{"Integer": 42}`,
+//!   `{"Keyword": "Local"}` or `{"Call": [func, args]}`.
+//! - Symbols and string contents are strings if they are valid UTF-8, and arrays of bytes otherwise.
+//! - Floats are numbers if they are finite, and the strings `"inf"`, `"-inf"` or `"nan"` otherwise.
+//!
+//! The names are those of the Rust types, fields and variants, so the representation only changes
+//! along with them.
+
+use std::fmt;
+use std::str;
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+use intern::Symbol;
+
+/// Serializes bytes as a string if they are valid UTF-8, and as bytes otherwise.
+fn serialize_bytes<S: Serializer>(val: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    match str::from_utf8(val) {
+        Ok(val) => serializer.serialize_str(val),
+        Err(_) => serializer.serialize_bytes(val),
+    }
+}
+
+/// Visits a string or a sequence of bytes.
+struct BytesVisitor;
+
+/// Implements `Visitor` for `BytesVisitor`.
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a string or an array of bytes")
+    }
+    fn visit_str<E: de::Error>(self, val: &str) -> Result<Vec<u8>, E> {
+        Ok(val.as_bytes().to_vec())
+    }
+    fn visit_bytes<E: de::Error>(self, val: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(val.to_vec())
+    }
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let mut val = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            val.push(byte);
+        }
+        Ok(val)
+    }
+}
+
+/// Implements `Serialize` for `Symbol`.
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_bytes(self.as_bytes(), serializer)
+    }
+}
+
+/// Implements `Deserialize` for `Symbol`.
+/// The string is interned.
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Symbol, D::Error> {
+        deserializer.deserialize_byte_buf(BytesVisitor).map(|val| Symbol::intern(&val))
+    }
+}
+
+/// Serialization of string contents, which are not necessarily UTF-8.
+pub(crate) mod bytes {
+    use std::borrow::Cow;
+    use serde::de::Deserializer;
+    use serde::ser::Serializer;
+    use super::{serialize_bytes, BytesVisitor};
+
+    /// Serializes string contents.
+    pub fn serialize<S: Serializer>(val: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_bytes(val, serializer)
+    }
+
+    /// Deserializes string contents, which are always owned.
+    pub fn deserialize<'de, 'a, D, T>(deserializer: D) -> Result<T, D::Error>
+        where D: Deserializer<'de>,
+              T: From<Cow<'a, [u8]>>
+    {
+        deserializer.deserialize_byte_buf(BytesVisitor).map(|val| T::from(Cow::Owned(val)))
+    }
+}
+
+/// Serialization of floats, which may not be finite.
+pub(crate) mod float {
+    use std::fmt;
+    use serde::de::{self, Deserializer, Visitor};
+    use serde::ser::Serializer;
+
+    /// Serializes a float.
+    pub fn serialize<S: Serializer>(val: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        match *val {
+            val if val.is_finite() => serializer.serialize_f64(val),
+            val if val.is_nan() => serializer.serialize_str("nan"),
+            val if val > 0.0 => serializer.serialize_str("inf"),
+            _ => serializer.serialize_str("-inf"),
+        }
+    }
+
+    /// Visits a number or the name of a non-finite float.
+    struct FloatVisitor;
+
+    /// Implements `Visitor` for `FloatVisitor`.
+    impl<'de> Visitor<'de> for FloatVisitor {
+        type Value = f64;
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a number, \"inf\", \"-inf\" or \"nan\"")
+        }
+        fn visit_f64<E: de::Error>(self, val: f64) -> Result<f64, E> {
+            Ok(val)
+        }
+        fn visit_i64<E: de::Error>(self, val: i64) -> Result<f64, E> {
+            Ok(val as f64)
+        }
+        fn visit_u64<E: de::Error>(self, val: u64) -> Result<f64, E> {
+            Ok(val as f64)
+        }
+        fn visit_str<E: de::Error>(self, val: &str) -> Result<f64, E> {
+            match val {
+                "inf" => Ok(f64::INFINITY),
+                "-inf" => Ok(f64::NEG_INFINITY),
+                "nan" => Ok(f64::NAN),
+                _ => Err(E::invalid_value(de::Unexpected::Str(val), &self)),
+            }
+        }
+    }
+
+    /// Deserializes a float.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        deserializer.deserialize_any(FloatVisitor)
+    }
+}
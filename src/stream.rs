@@ -0,0 +1,138 @@
+#![deny(dead_code)]
+#![deny(missing_docs)]
+
+//! Streaming lexical analysis.
+//! Lexes source that is read incrementally, e.g. from a file or a pipe.
+
+use std::io::{self, Read};
+use dialect::Options;
+use lexer::{Lexer, Lexeme, LexError, LexErrorKind, TokenPosition};
+
+/// The minimum number of bytes to read at once.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Streaming lexical analyser.
+/// Only the part of the source that has not been lexed yet is buffered,
+/// so tokens do not borrow from the source. Spans are byte offsets from the start of the stream.
+#[derive(Debug)]
+pub struct StreamLexer<R> {
+    /// The reader.
+    reader: R,
+    /// The dialect and extensions to accept.
+    options: Options,
+    /// The buffered part of the source.
+    buf: Vec<u8>,
+    /// The offset of the next byte to lex in the buffer.
+    offset: usize,
+    /// The number of bytes that have been dropped from the front of the buffer.
+    consumed: usize,
+    /// The current position.
+    pos: TokenPosition,
+    /// Whether the reader is exhausted.
+    eof: bool,
+    /// Whether an error has been reported.
+    failed: bool,
+}
+
+/// Implements `StreamLexer`.
+impl<R: Read> StreamLexer<R> {
+    /// Constructs a new `StreamLexer`.
+    pub fn new(reader: R) -> StreamLexer<R> {
+        StreamLexer::with_options(reader, Options::default())
+    }
+
+    /// Constructs a new `StreamLexer` that accepts the given dialect and extensions.
+    pub fn with_options(reader: R, options: Options) -> StreamLexer<R> {
+        StreamLexer {
+            reader,
+            options,
+            buf: Vec::new(),
+            offset: 0,
+            consumed: 0,
+            pos: TokenPosition::default(),
+            eof: false,
+            failed: false,
+        }
+    }
+
+    /// Drops the lexed part of the buffer and reads more of the source.
+    /// Reads at least as much as is buffered, so that long tokens are not lexed over and over.
+    fn fill(&mut self) -> io::Result<()> {
+        self.buf.drain(..self.offset);
+        self.consumed += self.offset;
+        self.offset = 0;
+        let len = self.buf.len();
+        self.buf.resize(len + CHUNK_SIZE.max(len), 0);
+        loop {
+            match self.reader.read(&mut self.buf[len..]) {
+                Ok(read) => {
+                    self.buf.truncate(len + read);
+                    self.eof = read == 0;
+                    return Ok(());
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => {
+                    self.buf.truncate(len);
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+/// Implements `Iterator` for `StreamLexer`.
+/// Yields an error at most once, after which the stream ends.
+impl<R: Read> Iterator for StreamLexer<R> {
+    type Item = Result<Lexeme<'static>, LexError>;
+
+    /// Reads the next `Item`.
+    fn next(&mut self) -> Option<Result<Lexeme<'static>, LexError>> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            let mut lexer = Lexer::resume(&self.buf, self.options, self.offset, self.pos);
+            let res = lexer.next();
+
+            // The lexer looks at most one byte past the end of a token, so a token is complete
+            // if it is followed by another byte. An error is only final if it was not caused
+            // by running out of input, which could also be because of a run of `=` in a long
+            // bracket that continues beyond the buffer.
+            let len = self.buf.len();
+            let done = self.eof || match res {
+                Some(Ok(ref lexeme)) => lexeme.2.end < len,
+                Some(Err(_)) => {
+                    lexer.offset() + 1 < len && self.buf[lexer.offset() + 1..].iter().any(|&chr| chr != b'=')
+                }
+                None => false,
+            };
+            if done {
+                let offset = lexer.offset();
+                let pos = lexer.position();
+                let consumed = self.consumed;
+                let res = res.map(|res| {
+                    res.map(|lexeme| {
+                        let mut lexeme = lexeme.into_owned();
+                        lexeme.move_by(&mut |_, span| {
+                            span.start += consumed;
+                            span.end += consumed;
+                        });
+                        lexeme
+                    })
+                });
+                self.offset = offset;
+                self.pos = pos;
+                self.failed = matches!(res, Some(Err(_)));
+                return res;
+            }
+
+            if let Err(err) = self.fill() {
+                self.failed = true;
+                return Some(Err(LexError {
+                    kind: LexErrorKind::Io(err.kind()),
+                    pos: self.pos,
+                }));
+            }
+        }
+    }
+}
@@ -1,45 +1,86 @@
+//! Lexical tokens.
+//! Produced by the lexical analyser.
+
+use std::borrow::Cow;
+use std::fmt;
+use dialect::Options;
+use intern::Symbol;
+use lexer::{Lexer, Lexeme};
+
 /// A keyword token.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Keyword {
+    /// The `and` keyword.
     And,
+    /// The `break` keyword.
     Break,
+    /// The `do` keyword.
     Do,
+    /// The `else` keyword.
     Else,
+    /// The `elseif` keyword.
     ElseIf,
+    /// The `end` keyword.
     End,
+    /// The `false` keyword.
     False,
+    /// The `for` keyword.
     For,
+    /// The `function` keyword.
     Function,
+    /// The `goto` keyword.
     Goto,
+    /// The `if` keyword.
     If,
+    /// The `in` keyword.
     In,
+    /// The `local` keyword.
     Local,
+    /// The `nil` keyword.
     Nil,
+    /// The `not` keyword.
     Not,
+    /// The `or` keyword.
     Or,
+    /// The `repeat` keyword.
     Repeat,
+    /// The `return` keyword.
     Return,
+    /// The `then` keyword.
     Then,
+    /// The `true` keyword.
     True,
+    /// The `until` keyword.
     Until,
+    /// The `while` keyword.
     While,
 }
 
 /// A lexical token.
-#[derive(Debug, PartialEq)]
-pub enum Token {
-    /// A number.
-    Number(f64),
+/// Strings and comments may borrow from the source, while identifiers are interned.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Token<'a> {
+    /// An integer number, e.g. `42` or `0xFF`.
+    Integer(i64),
+    /// A floating-point number, e.g. `4.2` or `42e1`.
+    Float(#[cfg_attr(feature = "serde", serde(with = "::serialize::float"))] f64),
     /// An identifier.
-    Ident(String),
+    Ident(Symbol),
     /// A keyword.
     Keyword(Keyword),
     /// A string literal.
-    StaticString(String),
+    /// Lua strings are arbitrary byte sequences, so this is not necessarily UTF-8.
+    StaticString(#[cfg_attr(feature = "serde", serde(with = "::serialize::bytes"))] Cow<'a, [u8]>),
     /// A comment.
-    Comment(String),
+    /// Invalid UTF-8 sequences are replaced with `U+FFFD`.
+    Comment(Cow<'a, str>),
     /// A hashbang.
-    Hashbang(String),
+    Hashbang(Cow<'a, str>),
+    /// An interpolated string, e.g. `$"a = {a}"`.
+    /// Only produced if the string interpolation extension is enabled.
+    Interpolation(Vec<Fragment<'a>>),
     /// The `+` operator.
     Add,
     /// The `-` operator.
@@ -48,6 +89,8 @@ pub enum Token {
     Mul,
     /// The `/` operator.
     Div,
+    /// The `//` operator.
+    FloorDiv,
     /// The `%` operator.
     Mod,
     /// The `^` operator.
@@ -82,10 +125,52 @@ pub enum Token {
     Concat,
     /// The `...` operator.
     VarArgs,
-    /// The `$` operator.
-    Dollar,
+    /// The `&` operator.
+    BitAnd,
     /// The `|` operator.
-    Lambda,
+    BitOr,
+    /// The `~` operator, binary exclusive or and unary bitwise not.
+    BitXorOrNot,
+    /// The `<<` operator.
+    ShiftLeft,
+    /// The `>>` operator.
+    ShiftRight,
+    /// The `+=` operator.
+    /// Only produced if the compound assignment extension is enabled.
+    AddAssign,
+    /// The `-=` operator.
+    /// Only produced if the compound assignment extension is enabled.
+    SubAssign,
+    /// The `*=` operator.
+    /// Only produced if the compound assignment extension is enabled.
+    MulAssign,
+    /// The `/=` operator.
+    /// Only produced if the compound assignment extension is enabled.
+    DivAssign,
+    /// The `//=` operator.
+    /// Only produced if the compound assignment extension is enabled.
+    FloorDivAssign,
+    /// The `%=` operator.
+    /// Only produced if the compound assignment extension is enabled.
+    ModAssign,
+    /// The `^=` operator.
+    /// Only produced if the compound assignment extension is enabled.
+    PowerAssign,
+    /// The `..=` operator.
+    /// Only produced if the compound assignment extension is enabled.
+    ConcatAssign,
+    /// The `&=` operator.
+    /// Only produced if the compound assignment extension is enabled.
+    BitAndAssign,
+    /// The `|=` operator.
+    /// Only produced if the compound assignment extension is enabled.
+    BitOrAssign,
+    /// The `<<=` operator.
+    /// Only produced if the compound assignment extension is enabled.
+    ShiftLeftAssign,
+    /// The `>>=` operator.
+    /// Only produced if the compound assignment extension is enabled.
+    ShiftRightAssign,
     /// The `{` operator.
     OpenBrace,
     /// The `}` operator.
@@ -100,16 +185,321 @@ pub enum Token {
     CloseBracket,
 }
 
+/// Implements `Token`.
+impl<'a> Token<'a> {
+    /// Converts the token into one that does not borrow from the source.
+    pub fn into_owned(self) -> Token<'static> {
+        match self {
+            Token::Integer(val) => Token::Integer(val),
+            Token::Float(val) => Token::Float(val),
+            Token::Ident(val) => Token::Ident(val),
+            Token::Keyword(val) => Token::Keyword(val),
+            Token::StaticString(val) => Token::StaticString(Cow::Owned(val.into_owned())),
+            Token::Comment(val) => Token::Comment(Cow::Owned(val.into_owned())),
+            Token::Hashbang(val) => Token::Hashbang(Cow::Owned(val.into_owned())),
+            Token::Interpolation(val) => Token::Interpolation(val.into_iter().map(Fragment::into_owned).collect()),
+            Token::Add => Token::Add,
+            Token::SubOrMinus => Token::SubOrMinus,
+            Token::Mul => Token::Mul,
+            Token::Div => Token::Div,
+            Token::FloorDiv => Token::FloorDiv,
+            Token::Mod => Token::Mod,
+            Token::Power => Token::Power,
+            Token::Len => Token::Len,
+            Token::Colon => Token::Colon,
+            Token::DoubleColon => Token::DoubleColon,
+            Token::Semicolon => Token::Semicolon,
+            Token::Comma => Token::Comma,
+            Token::Assignment => Token::Assignment,
+            Token::Equal => Token::Equal,
+            Token::NotEqual => Token::NotEqual,
+            Token::LessThan => Token::LessThan,
+            Token::LessThanEqual => Token::LessThanEqual,
+            Token::GreaterThan => Token::GreaterThan,
+            Token::GreaterThanEqual => Token::GreaterThanEqual,
+            Token::MemberAccess => Token::MemberAccess,
+            Token::Concat => Token::Concat,
+            Token::VarArgs => Token::VarArgs,
+            Token::BitAnd => Token::BitAnd,
+            Token::BitOr => Token::BitOr,
+            Token::BitXorOrNot => Token::BitXorOrNot,
+            Token::ShiftLeft => Token::ShiftLeft,
+            Token::ShiftRight => Token::ShiftRight,
+            Token::AddAssign => Token::AddAssign,
+            Token::SubAssign => Token::SubAssign,
+            Token::MulAssign => Token::MulAssign,
+            Token::DivAssign => Token::DivAssign,
+            Token::FloorDivAssign => Token::FloorDivAssign,
+            Token::ModAssign => Token::ModAssign,
+            Token::PowerAssign => Token::PowerAssign,
+            Token::ConcatAssign => Token::ConcatAssign,
+            Token::BitAndAssign => Token::BitAndAssign,
+            Token::BitOrAssign => Token::BitOrAssign,
+            Token::ShiftLeftAssign => Token::ShiftLeftAssign,
+            Token::ShiftRightAssign => Token::ShiftRightAssign,
+            Token::OpenBrace => Token::OpenBrace,
+            Token::CloseBrace => Token::CloseBrace,
+            Token::OpenParen => Token::OpenParen,
+            Token::CloseParen => Token::CloseParen,
+            Token::OpenBracket => Token::OpenBracket,
+            Token::CloseBracket => Token::CloseBracket,
+        }
+    }
+}
+
+/// A part of an interpolated string.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Fragment<'a> {
+    /// Literal text, with escape sequences and doubled braces resolved.
+    Text(#[cfg_attr(feature = "serde", serde(with = "::serialize::bytes"))] Cow<'a, [u8]>),
+    /// An embedded expression, as the tokens between the braces.
+    Expr(Vec<Lexeme<'a>>),
+}
+
+/// Implements `Fragment`.
+impl<'a> Fragment<'a> {
+    /// Converts the fragment into one that does not borrow from the source.
+    pub fn into_owned(self) -> Fragment<'static> {
+        match self {
+            Fragment::Text(val) => Fragment::Text(Cow::Owned(val.into_owned())),
+            Fragment::Expr(val) => Fragment::Expr(val.into_iter().map(Lexeme::into_owned).collect()),
+        }
+    }
+}
+
 /// Implements `From<&'a str>` for `Token`.
-impl<'a> From<&'a str> for Token {
-    fn from(val: &'a str) -> Token {
-        Token::Ident(val.to_string())
+impl<'a> From<&'a str> for Token<'a> {
+    fn from(val: &'a str) -> Token<'a> {
+        Token::Ident(Symbol::intern(val))
     }
 }
 
 /// Implements `From<Keyword>` for `Token`.
-impl From<Keyword> for Token {
-    fn from(val: Keyword) -> Token {
+impl<'a> From<Keyword> for Token<'a> {
+    fn from(val: Keyword) -> Token<'a> {
         Token::Keyword(val)
     }
-}
\ No newline at end of file
+}
+/// Implements `Display` for `Keyword`.
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Keyword::And => "and",
+            Keyword::Break => "break",
+            Keyword::Do => "do",
+            Keyword::Else => "else",
+            Keyword::ElseIf => "elseif",
+            Keyword::End => "end",
+            Keyword::False => "false",
+            Keyword::For => "for",
+            Keyword::Function => "function",
+            Keyword::Goto => "goto",
+            Keyword::If => "if",
+            Keyword::In => "in",
+            Keyword::Local => "local",
+            Keyword::Nil => "nil",
+            Keyword::Not => "not",
+            Keyword::Or => "or",
+            Keyword::Repeat => "repeat",
+            Keyword::Return => "return",
+            Keyword::Then => "then",
+            Keyword::True => "true",
+            Keyword::Until => "until",
+            Keyword::While => "while",
+        })
+    }
+}
+
+/// Writes the contents of a string literal between the given quotes, escaping everything that is not printable.
+/// Braces are doubled in interpolated strings.
+fn write_string_contents(f: &mut fmt::Formatter, val: &[u8], quote: char, interpolated: bool) -> fmt::Result {
+    for chunk in val.utf8_chunks() {
+        for chr in chunk.valid().chars() {
+            match chr {
+                '{' if interpolated => f.write_str("{{")?,
+                '}' if interpolated => f.write_str("}}")?,
+                chr if chr == quote => write!(f, "\\{}", chr)?,
+                '\\' => f.write_str("\\\\")?,
+                '\x07' => f.write_str("\\a")?,
+                '\x08' => f.write_str("\\b")?,
+                '\x0b' => f.write_str("\\v")?,
+                '\x0c' => f.write_str("\\f")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                // Decimal escapes have at most three digits, so padding them
+                // keeps a following digit from being read as part of the escape.
+                chr if chr.is_ascii_control() => write!(f, "\\{:03}", chr as u32)?,
+                chr => write!(f, "{}", chr)?,
+            }
+        }
+        for &byte in chunk.invalid() {
+            write!(f, "\\{:03}", byte)?;
+        }
+    }
+    Ok(())
+}
+
+/// A string literal between the given quotes.
+pub(crate) struct Quoted<'a>(pub &'a [u8], pub char);
+
+/// Implements `Display` for `Quoted`.
+impl<'a> fmt::Display for Quoted<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.1)?;
+        write_string_contents(f, self.0, self.1, false)?;
+        write!(f, "{}", self.1)
+    }
+}
+
+/// Writes a long comment, using the lowest level that does not clash with its contents.
+fn write_long_comment(f: &mut fmt::Formatter, val: &str) -> fmt::Result {
+    let mut level = 0;
+    loop {
+        let close = format!("]{}]", "=".repeat(level));
+        if !format!("{}]", val).contains(&close) {
+            break;
+        }
+        level += 1;
+    }
+    let level = "=".repeat(level);
+    // A newline directly following the opening bracket is skipped by the lexer.
+    let newline = if val.starts_with('\n') || val.starts_with('\r') { "\n" } else { "" };
+    write!(f, "--[{}[{}{}]{}]", level, newline, val, level)
+}
+
+/// Implements `Display` for `Token`.
+/// Prints the canonical spelling, which is lexed as the same token again.
+impl<'a> fmt::Display for Token<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match *self {
+            // Negative integers are written in hexadecimal, which wraps around.
+            Token::Integer(val) if val < 0 => return write!(f, "0x{:X}", val as u64),
+            Token::Integer(val) => return write!(f, "{}", val),
+            Token::Float(val) if val.is_nan() => "(0/0)",
+            Token::Float(val) if val.is_infinite() && val > 0.0 => "1e9999",
+            Token::Float(val) if val.is_infinite() => "-1e9999",
+            // The debug representation is the shortest one that round-trips and always
+            // contains a `.` or an exponent, so it is not read as an integer.
+            Token::Float(val) => return write!(f, "{:?}", val),
+            Token::Ident(ref val) => return write!(f, "{}", val),
+            Token::Keyword(val) => return write!(f, "{}", val),
+            Token::StaticString(ref val) => return write!(f, "{}", Quoted(val, '"')),
+            Token::Interpolation(ref fragments) => {
+                f.write_str("$\"")?;
+                for fragment in fragments {
+                    match *fragment {
+                        Fragment::Text(ref val) => write_string_contents(f, val, '"', true)?,
+                        Fragment::Expr(ref tokens) => {
+                            write!(f, "{{{}}}", to_source(tokens.iter().map(|lexeme| &lexeme.0)))?
+                        }
+                    }
+                }
+                return f.write_str("\"");
+            }
+            Token::Comment(ref val) => {
+                let is_line = !val.contains(&['\n', '\r'][..]) &&
+                              !val.starts_with(|chr: char| chr.is_ascii_whitespace());
+                return if !is_line {
+                    write_long_comment(f, val)
+                } else if val.is_empty() {
+                    f.write_str("--")
+                } else {
+                    write!(f, "-- {}", val)
+                };
+            }
+            Token::Hashbang(ref val) => return write!(f, "#!{}", val),
+            Token::Add => "+",
+            Token::SubOrMinus => "-",
+            Token::Mul => "*",
+            Token::Div => "/",
+            Token::FloorDiv => "//",
+            Token::Mod => "%",
+            Token::Power => "^",
+            Token::Len => "#",
+            Token::Colon => ":",
+            Token::DoubleColon => "::",
+            Token::Semicolon => ";",
+            Token::Comma => ",",
+            Token::Assignment => "=",
+            Token::Equal => "==",
+            Token::NotEqual => "~=",
+            Token::LessThan => "<",
+            Token::LessThanEqual => "<=",
+            Token::GreaterThan => ">",
+            Token::GreaterThanEqual => ">=",
+            Token::MemberAccess => ".",
+            Token::Concat => "..",
+            Token::VarArgs => "...",
+            Token::BitAnd => "&",
+            Token::BitOr => "|",
+            Token::BitXorOrNot => "~",
+            Token::ShiftLeft => "<<",
+            Token::ShiftRight => ">>",
+            Token::AddAssign => "+=",
+            Token::SubAssign => "-=",
+            Token::MulAssign => "*=",
+            Token::DivAssign => "/=",
+            Token::FloorDivAssign => "//=",
+            Token::ModAssign => "%=",
+            Token::PowerAssign => "^=",
+            Token::ConcatAssign => "..=",
+            Token::BitAndAssign => "&=",
+            Token::BitOrAssign => "|=",
+            Token::ShiftLeftAssign => "<<=",
+            Token::ShiftRightAssign => ">>=",
+            Token::OpenBrace => "{",
+            Token::CloseBrace => "}",
+            Token::OpenParen => "(",
+            Token::CloseParen => ")",
+            Token::OpenBracket => "[",
+            Token::CloseBracket => "]",
+        };
+        f.write_str(op)
+    }
+}
+
+/// Converts tokens back into source code.
+/// Tokens are only separated by a space where they would otherwise be lexed differently,
+/// and line comments and the hashbang are followed by a newline.
+pub fn to_source<'a, 'b: 'a, I: IntoIterator<Item = &'a Token<'b>>>(tokens: I) -> String {
+    let options = Options {
+        lambda: true,
+        interpolation: true,
+        compound_assignment: true,
+        ..Options::default()
+    };
+    let mut buf = String::new();
+    let mut prev: Option<(&Token, String)> = None;
+    for token in tokens {
+        let text = token.to_string();
+        if let Some((prev, ref prev_text)) = prev {
+            let is_line = match *prev {
+                Token::Comment(_) => !prev_text.starts_with("--["),
+                Token::Hashbang(_) => true,
+                _ => false,
+            };
+            if is_line {
+                buf.push('\n');
+            } else {
+                let joined = format!("{}{}", prev_text, text);
+                let mut lexer = Lexer::with_options(&joined, options);
+                let separate = match (lexer.next(), lexer.next(), lexer.next()) {
+                    // Tokens are compared by their spelling, since embedded
+                    // expressions in interpolated strings would be at different positions.
+                    (Some(Ok(Lexeme(ref a, ..))), Some(Ok(Lexeme(ref b, ..))), None) => {
+                        a.to_string() != *prev_text || b.to_string() != text
+                    }
+                    _ => true,
+                };
+                if separate {
+                    buf.push(' ');
+                }
+            }
+        }
+        buf.push_str(&text);
+        prev = Some((token, text));
+    }
+    buf
+}
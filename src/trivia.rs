@@ -0,0 +1,218 @@
+#![deny(dead_code)]
+#![deny(missing_docs)]
+
+//! Lossless lexical analysis.
+//! Attaches whitespace and comments to the surrounding tokens,
+//! so that the source can be reconstructed byte-for-byte.
+
+use std::iter::Peekable;
+use dialect::Options;
+use lexer::{is_whitespace, Lexer, Lexeme, LexError, Span};
+use token::Token;
+
+/// The kind of a piece of trivia.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+    /// A run of whitespace other than newlines.
+    Whitespace,
+    /// A single newline sequence, i.e. `\n`, `\r`, `\r\n` or `\n\r`.
+    Newline,
+    /// A line or long comment, including its delimiters.
+    Comment,
+    /// A hashbang, including the leading `#!`.
+    Hashbang,
+}
+
+/// Source text that does not affect the meaning of the program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia<'a> {
+    /// The kind of trivia.
+    pub kind: TriviaKind,
+    /// The exact source text.
+    pub text: &'a [u8],
+    /// The location in the source.
+    pub span: Span,
+}
+
+/// A lexeme together with its surrounding trivia.
+/// Trailing trivia extends up to and including the end of the line the token ends on,
+/// everything after that is leading trivia of the next token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriviaLexeme<'a> {
+    /// The trivia preceding the token.
+    pub leading: Vec<Trivia<'a>>,
+    /// The lexeme, or `None` for the end of the stream, which only carries leading trivia.
+    pub lexeme: Option<Lexeme<'a>>,
+    /// The exact source text of the token.
+    pub text: &'a [u8],
+    /// The trivia following the token on the same line.
+    pub trailing: Vec<Trivia<'a>>,
+}
+
+/// Implements `TriviaLexeme`.
+impl<'a> TriviaLexeme<'a> {
+    /// Appends the exact source text of the trivia and the token to a buffer.
+    pub fn write_to(&self, buf: &mut Vec<u8>) {
+        for trivia in &self.leading {
+            buf.extend_from_slice(trivia.text);
+        }
+        buf.extend_from_slice(self.text);
+        for trivia in &self.trailing {
+            buf.extend_from_slice(trivia.text);
+        }
+    }
+}
+
+/// Lossless lexical analyser.
+/// Yields every token with its trivia, followed by a final `TriviaLexeme` without a lexeme
+/// that holds the trivia at the end of the source. Comments and the hashbang are
+/// reported as trivia rather than as tokens.
+#[derive(Debug, Clone)]
+pub struct LosslessLexer<'a> {
+    /// The source.
+    src: &'a [u8],
+    /// The underlying lexer.
+    lexer: Peekable<Lexer<'a>>,
+    /// The byte offset up to which the source has been attached to a token.
+    offset: usize,
+    /// Whether the end of the stream or an error has been reported.
+    done: bool,
+}
+
+/// Implements `LosslessLexer`.
+impl<'a> LosslessLexer<'a> {
+    /// Constructs a new `LosslessLexer`.
+    pub fn new<S: AsRef<[u8]> + ?Sized>(src: &'a S) -> LosslessLexer<'a> {
+        LosslessLexer::with_options(src, Options::default())
+    }
+
+    /// Constructs a new `LosslessLexer` that accepts the given dialect and extensions.
+    pub fn with_options<S: AsRef<[u8]> + ?Sized>(src: &'a S, options: Options) -> LosslessLexer<'a> {
+        let src = src.as_ref();
+        LosslessLexer {
+            src,
+            lexer: Lexer::with_options(src, options).peekable(),
+            offset: 0,
+            done: false,
+        }
+    }
+
+    /// Creates a piece of trivia spanning from the current offset to `end` and advances past it.
+    fn take(&mut self, kind: TriviaKind, end: usize) -> Trivia<'a> {
+        let span = Span { start: self.offset, end };
+        self.offset = end;
+        Trivia {
+            kind,
+            text: span.text(self.src),
+            span,
+        }
+    }
+
+    /// Reads whitespace up to `end` as trivia.
+    /// Stops after the first newline sequence if `stop_at_newline` is set.
+    /// Returns whether a newline sequence was read.
+    fn read_whitespace(&mut self, end: usize, stop_at_newline: bool, trivia: &mut Vec<Trivia<'a>>) -> bool {
+        let src = self.src;
+        while self.offset < end {
+            match src[self.offset] {
+                chr @ b'\n' | chr @ b'\r' => {
+                    let mut len = 1;
+                    match src.get(self.offset + 1) {
+                        Some(&next) if (next == b'\n' || next == b'\r') && next != chr => len = 2,
+                        _ => (),
+                    }
+                    let newline = self.take(TriviaKind::Newline, self.offset + len);
+                    trivia.push(newline);
+                    if stop_at_newline {
+                        return true;
+                    }
+                }
+                _ => {
+                    let len = src[self.offset..end]
+                        .iter()
+                        .take_while(|&&chr| chr != b'\n' && chr != b'\r')
+                        .count();
+                    let whitespace = self.take(TriviaKind::Whitespace, self.offset + len);
+                    trivia.push(whitespace);
+                }
+            }
+        }
+        false
+    }
+
+    /// Determines the end of the whitespace following the current offset.
+    fn whitespace_end(&self) -> usize {
+        let len = self.src[self.offset..]
+            .iter()
+            .take_while(|&&chr| is_whitespace(chr))
+            .count();
+        self.offset + len
+    }
+}
+
+/// Implements `Iterator` for `LosslessLexer`.
+/// Yields an error at most once, after which the stream ends.
+impl<'a> Iterator for LosslessLexer<'a> {
+    type Item = Result<TriviaLexeme<'a>, LexError>;
+
+    /// Reads the next `Item`.
+    fn next(&mut self) -> Option<Result<TriviaLexeme<'a>, LexError>> {
+        if self.done {
+            return None;
+        }
+
+        // Read the leading trivia up to the next token.
+        let mut leading = Vec::new();
+        let lexeme = loop {
+            let lexeme = match self.lexer.next() {
+                Some(Ok(lexeme)) => lexeme,
+                Some(Err(err)) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+                None => {
+                    let end = self.src.len();
+                    self.read_whitespace(end, false, &mut leading);
+                    self.done = true;
+                    return Some(Ok(TriviaLexeme {
+                        leading,
+                        lexeme: None,
+                        text: &[],
+                        trailing: Vec::new(),
+                    }));
+                }
+            };
+            self.read_whitespace(lexeme.2.start, false, &mut leading);
+            match lexeme.0 {
+                Token::Comment(_) => leading.push(self.take(TriviaKind::Comment, lexeme.2.end)),
+                Token::Hashbang(_) => leading.push(self.take(TriviaKind::Hashbang, lexeme.2.end)),
+                _ => break lexeme,
+            }
+        };
+        let text = lexeme.2.text(self.src);
+        self.offset = lexeme.2.end;
+
+        // Read the trailing trivia up to the end of the line.
+        let mut trailing = Vec::new();
+        loop {
+            let end = self.whitespace_end();
+            if self.read_whitespace(end, true, &mut trailing) {
+                break;
+            }
+            match self.lexer.peek() {
+                Some(&Ok(Lexeme(Token::Comment(_), _, span))) if span.start == self.offset => {
+                    trailing.push(self.take(TriviaKind::Comment, span.end));
+                    self.lexer.next();
+                }
+                _ => break,
+            }
+        }
+
+        Some(Ok(TriviaLexeme {
+            leading,
+            lexeme: Some(lexeme),
+            text,
+            trailing,
+        }))
+    }
+}
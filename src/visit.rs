@@ -0,0 +1,456 @@
+#![deny(dead_code)]
+#![deny(missing_docs)]
+
+//! Traversal of the abstract syntax tree.
+//! A pass implements `Visitor` or `VisitorMut` and overrides only the nodes it cares about.
+//! The default methods call the matching `walk` function, which visits the children of the node.
+//! An overriding method can call the `walk` function itself to continue into the children.
+
+use parser::ast::*;
+
+/// Visitor for a shared AST.
+#[allow(unused_variables)]
+pub trait Visitor<'ast> {
+    /// Visits a block.
+    fn visit_block(&mut self, block: &'ast Block) {
+        walk_block(self, block)
+    }
+    /// Visits a statement.
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        walk_stmt(self, stmt)
+    }
+    /// Visits an expression.
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        walk_expr(self, expr)
+    }
+    /// Visits a name.
+    fn visit_name(&mut self, name: &'ast Name) {}
+    /// Visits the attribute of a local variable.
+    fn visit_attrib(&mut self, attrib: &'ast Attrib) {}
+    /// Visits the name of a function in a function statement.
+    fn visit_func_name(&mut self, name: &'ast FuncName) {
+        walk_func_name(self, name)
+    }
+    /// Visits the parameters and the body of a function.
+    fn visit_function_body(&mut self, body: &'ast FunctionBody) {
+        walk_function_body(self, body)
+    }
+    /// Visits the target of an assignment.
+    fn visit_lvalue(&mut self, lvalue: &'ast LValue) {
+        walk_lvalue(self, lvalue)
+    }
+    /// Visits a table constructor.
+    fn visit_table(&mut self, table: &'ast TableConstructor) {
+        walk_table(self, table)
+    }
+    /// Visits a field in a table constructor.
+    fn visit_table_field(&mut self, field: &'ast TableField) {
+        walk_table_field(self, field)
+    }
+}
+
+/// Visits the statements of a block.
+pub fn walk_block<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, block: &'ast Block) {
+    for stmt in &block.0 {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+/// Visits the children of a statement.
+pub fn walk_stmt<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, stmt: &'ast Stmt) {
+    match stmt.kind {
+        StmtKind::Do(ref block) => visitor.visit_block(block),
+        StmtKind::Set(ref targets, ref exprs) => {
+            for target in targets {
+                visitor.visit_lvalue(target);
+            }
+            for expr in exprs {
+                visitor.visit_expr(expr);
+            }
+        }
+        StmtKind::Expr(ref expr) => visitor.visit_expr(expr),
+        StmtKind::While(ref cond, ref block) => {
+            visitor.visit_expr(cond);
+            visitor.visit_block(block);
+        }
+        StmtKind::Repeat(ref cond, ref block) => {
+            visitor.visit_block(block);
+            visitor.visit_expr(cond);
+        }
+        StmtKind::If(ref branches, ref otherwise) => {
+            for (cond, block) in branches {
+                visitor.visit_expr(cond);
+                visitor.visit_block(block);
+            }
+            if let Some(ref block) = *otherwise {
+                visitor.visit_block(block);
+            }
+        }
+        StmtKind::ForNum(ref name, ref start, ref end, ref step, ref block) => {
+            visitor.visit_name(name);
+            visitor.visit_expr(start);
+            visitor.visit_expr(end);
+            if let Some(ref step) = *step {
+                visitor.visit_expr(step);
+            }
+            visitor.visit_block(block);
+        }
+        StmtKind::ForIn(ref names, ref exprs, ref block) => {
+            for name in names {
+                visitor.visit_name(name);
+            }
+            for expr in exprs {
+                visitor.visit_expr(expr);
+            }
+            visitor.visit_block(block);
+        }
+        StmtKind::Function(ref name, ref body) => {
+            visitor.visit_func_name(name);
+            visitor.visit_function_body(body);
+        }
+        StmtKind::LocalFunction(ref name, ref body) => {
+            visitor.visit_name(name);
+            visitor.visit_function_body(body);
+        }
+        StmtKind::Local(ref names, ref exprs) => {
+            for (name, attrib) in names {
+                visitor.visit_name(name);
+                if let Some(ref attrib) = *attrib {
+                    visitor.visit_attrib(attrib);
+                }
+            }
+            for expr in exprs {
+                visitor.visit_expr(expr);
+            }
+        }
+        StmtKind::Goto(ref name) |
+        StmtKind::Label(ref name) => visitor.visit_name(name),
+        StmtKind::Return(ref exprs) => {
+            for expr in exprs {
+                visitor.visit_expr(expr);
+            }
+        }
+        StmtKind::Break | StmtKind::Error => (),
+    }
+}
+
+/// Visits the children of an expression.
+pub fn walk_expr<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, expr: &'ast Expr) {
+    match expr.kind {
+        ExprKind::Nil |
+        ExprKind::Dots |
+        ExprKind::True |
+        ExprKind::False |
+        ExprKind::Integer(_) |
+        ExprKind::Float(_) |
+        ExprKind::StaticString(_) => (),
+        ExprKind::Name(ref name) => visitor.visit_name(name),
+        ExprKind::Paren(ref expr) |
+        ExprKind::Unary(_, ref expr) |
+        ExprKind::Not(ref expr) |
+        ExprKind::Len(ref expr) => visitor.visit_expr(expr),
+        ExprKind::Field(ref table, ref name) => {
+            visitor.visit_expr(table);
+            visitor.visit_name(name);
+        }
+        ExprKind::Index(ref lhs, ref rhs) |
+        ExprKind::Operation(_, ref lhs, ref rhs) |
+        ExprKind::Concat(ref lhs, ref rhs) |
+        ExprKind::And(ref lhs, ref rhs) |
+        ExprKind::Or(ref lhs, ref rhs) => {
+            visitor.visit_expr(lhs);
+            visitor.visit_expr(rhs);
+        }
+        ExprKind::Call(ref func, ref args) => {
+            visitor.visit_expr(func);
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+        ExprKind::MethodCall(ref object, ref name, ref args) => {
+            visitor.visit_expr(object);
+            visitor.visit_name(name);
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+        ExprKind::Function(ref body) => visitor.visit_function_body(body),
+        ExprKind::Table(ref table) => visitor.visit_table(table),
+    }
+}
+
+/// Visits the names in the name of a function.
+pub fn walk_func_name<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, name: &'ast FuncName) {
+    for name in name.path.iter().chain(name.method.iter()) {
+        visitor.visit_name(name);
+    }
+}
+
+/// Visits the parameters and the body of a function.
+pub fn walk_function_body<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, body: &'ast FunctionBody) {
+    for param in &body.params {
+        visitor.visit_name(param);
+    }
+    visitor.visit_block(&body.block);
+}
+
+/// Visits the children of the target of an assignment.
+pub fn walk_lvalue<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, lvalue: &'ast LValue) {
+    match *lvalue {
+        LValue::Name(ref name) => visitor.visit_name(name),
+        LValue::Field(ref table, ref name) => {
+            visitor.visit_expr(table);
+            visitor.visit_name(name);
+        }
+        LValue::Index(ref table, ref key) => {
+            visitor.visit_expr(table);
+            visitor.visit_expr(key);
+        }
+    }
+}
+
+/// Visits the fields of a table constructor.
+pub fn walk_table<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, table: &'ast TableConstructor) {
+    for field in &table.0 {
+        visitor.visit_table_field(field);
+    }
+}
+
+/// Visits the children of a field in a table constructor.
+pub fn walk_table_field<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, field: &'ast TableField) {
+    match *field {
+        TableField::Positional(ref value) => visitor.visit_expr(value),
+        TableField::Named(ref name, ref value) => {
+            visitor.visit_name(name);
+            visitor.visit_expr(value);
+        }
+        TableField::Bracketed(ref key, ref value) => {
+            visitor.visit_expr(key);
+            visitor.visit_expr(value);
+        }
+    }
+}
+
+/// Visitor for a mutable AST.
+/// Mirrors `Visitor`, so that passes can rewrite nodes in place.
+#[allow(unused_variables)]
+pub trait VisitorMut {
+    /// Visits a block.
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        walk_block_mut(self, block)
+    }
+    /// Visits a statement.
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt)
+    }
+    /// Visits an expression.
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr)
+    }
+    /// Visits a name.
+    fn visit_name_mut(&mut self, name: &mut Name) {}
+    /// Visits the attribute of a local variable.
+    fn visit_attrib_mut(&mut self, attrib: &mut Attrib) {}
+    /// Visits the name of a function in a function statement.
+    fn visit_func_name_mut(&mut self, name: &mut FuncName) {
+        walk_func_name_mut(self, name)
+    }
+    /// Visits the parameters and the body of a function.
+    fn visit_function_body_mut(&mut self, body: &mut FunctionBody) {
+        walk_function_body_mut(self, body)
+    }
+    /// Visits the target of an assignment.
+    fn visit_lvalue_mut(&mut self, lvalue: &mut LValue) {
+        walk_lvalue_mut(self, lvalue)
+    }
+    /// Visits a table constructor.
+    fn visit_table_mut(&mut self, table: &mut TableConstructor) {
+        walk_table_mut(self, table)
+    }
+    /// Visits a field in a table constructor.
+    fn visit_table_field_mut(&mut self, field: &mut TableField) {
+        walk_table_field_mut(self, field)
+    }
+}
+
+/// Visits the statements of a block.
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, block: &mut Block) {
+    for stmt in &mut block.0 {
+        visitor.visit_stmt_mut(stmt);
+    }
+}
+
+/// Visits the children of a statement.
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(visitor: &mut V, stmt: &mut Stmt) {
+    match stmt.kind {
+        StmtKind::Do(ref mut block) => visitor.visit_block_mut(block),
+        StmtKind::Set(ref mut targets, ref mut exprs) => {
+            for target in targets {
+                visitor.visit_lvalue_mut(target);
+            }
+            for expr in exprs {
+                visitor.visit_expr_mut(expr);
+            }
+        }
+        StmtKind::Expr(ref mut expr) => visitor.visit_expr_mut(expr),
+        StmtKind::While(ref mut cond, ref mut block) => {
+            visitor.visit_expr_mut(cond);
+            visitor.visit_block_mut(block);
+        }
+        StmtKind::Repeat(ref mut cond, ref mut block) => {
+            visitor.visit_block_mut(block);
+            visitor.visit_expr_mut(cond);
+        }
+        StmtKind::If(ref mut branches, ref mut otherwise) => {
+            for (cond, block) in branches {
+                visitor.visit_expr_mut(cond);
+                visitor.visit_block_mut(block);
+            }
+            if let Some(ref mut block) = *otherwise {
+                visitor.visit_block_mut(block);
+            }
+        }
+        StmtKind::ForNum(ref mut name, ref mut start, ref mut end, ref mut step, ref mut block) => {
+            visitor.visit_name_mut(name);
+            visitor.visit_expr_mut(start);
+            visitor.visit_expr_mut(end);
+            if let Some(ref mut step) = *step {
+                visitor.visit_expr_mut(step);
+            }
+            visitor.visit_block_mut(block);
+        }
+        StmtKind::ForIn(ref mut names, ref mut exprs, ref mut block) => {
+            for name in names {
+                visitor.visit_name_mut(name);
+            }
+            for expr in exprs {
+                visitor.visit_expr_mut(expr);
+            }
+            visitor.visit_block_mut(block);
+        }
+        StmtKind::Function(ref mut name, ref mut body) => {
+            visitor.visit_func_name_mut(name);
+            visitor.visit_function_body_mut(body);
+        }
+        StmtKind::LocalFunction(ref mut name, ref mut body) => {
+            visitor.visit_name_mut(name);
+            visitor.visit_function_body_mut(body);
+        }
+        StmtKind::Local(ref mut names, ref mut exprs) => {
+            for (name, attrib) in names {
+                visitor.visit_name_mut(name);
+                if let Some(ref mut attrib) = *attrib {
+                    visitor.visit_attrib_mut(attrib);
+                }
+            }
+            for expr in exprs {
+                visitor.visit_expr_mut(expr);
+            }
+        }
+        StmtKind::Goto(ref mut name) |
+        StmtKind::Label(ref mut name) => visitor.visit_name_mut(name),
+        StmtKind::Return(ref mut exprs) => {
+            for expr in exprs {
+                visitor.visit_expr_mut(expr);
+            }
+        }
+        StmtKind::Break | StmtKind::Error => (),
+    }
+}
+
+/// Visits the children of an expression.
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match expr.kind {
+        ExprKind::Nil |
+        ExprKind::Dots |
+        ExprKind::True |
+        ExprKind::False |
+        ExprKind::Integer(_) |
+        ExprKind::Float(_) |
+        ExprKind::StaticString(_) => (),
+        ExprKind::Name(ref mut name) => visitor.visit_name_mut(name),
+        ExprKind::Paren(ref mut expr) |
+        ExprKind::Unary(_, ref mut expr) |
+        ExprKind::Not(ref mut expr) |
+        ExprKind::Len(ref mut expr) => visitor.visit_expr_mut(expr),
+        ExprKind::Field(ref mut table, ref mut name) => {
+            visitor.visit_expr_mut(table);
+            visitor.visit_name_mut(name);
+        }
+        ExprKind::Index(ref mut lhs, ref mut rhs) |
+        ExprKind::Operation(_, ref mut lhs, ref mut rhs) |
+        ExprKind::Concat(ref mut lhs, ref mut rhs) |
+        ExprKind::And(ref mut lhs, ref mut rhs) |
+        ExprKind::Or(ref mut lhs, ref mut rhs) => {
+            visitor.visit_expr_mut(lhs);
+            visitor.visit_expr_mut(rhs);
+        }
+        ExprKind::Call(ref mut func, ref mut args) => {
+            visitor.visit_expr_mut(func);
+            for arg in args {
+                visitor.visit_expr_mut(arg);
+            }
+        }
+        ExprKind::MethodCall(ref mut object, ref mut name, ref mut args) => {
+            visitor.visit_expr_mut(object);
+            visitor.visit_name_mut(name);
+            for arg in args {
+                visitor.visit_expr_mut(arg);
+            }
+        }
+        ExprKind::Function(ref mut body) => visitor.visit_function_body_mut(body),
+        ExprKind::Table(ref mut table) => visitor.visit_table_mut(table),
+    }
+}
+
+/// Visits the names in the name of a function.
+pub fn walk_func_name_mut<V: VisitorMut + ?Sized>(visitor: &mut V, name: &mut FuncName) {
+    for name in name.path.iter_mut().chain(name.method.iter_mut()) {
+        visitor.visit_name_mut(name);
+    }
+}
+
+/// Visits the parameters and the body of a function.
+pub fn walk_function_body_mut<V: VisitorMut + ?Sized>(visitor: &mut V, body: &mut FunctionBody) {
+    for param in &mut body.params {
+        visitor.visit_name_mut(param);
+    }
+    visitor.visit_block_mut(&mut body.block);
+}
+
+/// Visits the children of the target of an assignment.
+pub fn walk_lvalue_mut<V: VisitorMut + ?Sized>(visitor: &mut V, lvalue: &mut LValue) {
+    match *lvalue {
+        LValue::Name(ref mut name) => visitor.visit_name_mut(name),
+        LValue::Field(ref mut table, ref mut name) => {
+            visitor.visit_expr_mut(table);
+            visitor.visit_name_mut(name);
+        }
+        LValue::Index(ref mut table, ref mut key) => {
+            visitor.visit_expr_mut(table);
+            visitor.visit_expr_mut(key);
+        }
+    }
+}
+
+/// Visits the fields of a table constructor.
+pub fn walk_table_mut<V: VisitorMut + ?Sized>(visitor: &mut V, table: &mut TableConstructor) {
+    for field in &mut table.0 {
+        visitor.visit_table_field_mut(field);
+    }
+}
+
+/// Visits the children of a field in a table constructor.
+pub fn walk_table_field_mut<V: VisitorMut + ?Sized>(visitor: &mut V, field: &mut TableField) {
+    match *field {
+        TableField::Positional(ref mut value) => visitor.visit_expr_mut(value),
+        TableField::Named(ref mut name, ref mut value) => {
+            visitor.visit_name_mut(name);
+            visitor.visit_expr_mut(value);
+        }
+        TableField::Bracketed(ref mut key, ref mut value) => {
+            visitor.visit_expr_mut(key);
+            visitor.visit_expr_mut(value);
+        }
+    }
+}